@@ -1,46 +1,215 @@
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
 use clap::ArgMatches;
+use rayon::prelude::*;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use shlex::split;
+use slog::{debug, error, info, o, trace, warn, Drain, Level, Logger};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::fs;
-use std::fs::File;
-use std::io::Read;
+use std::fs::{File, FileTimes, OpenOptions};
+use std::io;
+use std::io::{BufRead, IsTerminal, Read, Seek, SeekFrom};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{
+    fchown, DirBuilderExt, FileExt, MetadataExt, OpenOptionsExt, PermissionsExt,
+};
 use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{
     io::Write,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 /**************************** rust_add starts **************************** */
 pub fn run_add(config: &AddConfig) -> Result<(), &'static str> {
     let v_dirs: Vec<PathBuf> = config.parse_dirs()?;
-    let v_files: Vec<&str> = config.parse_files()?;
+    let v_files: Vec<String> = config.parse_files()?;
+    let times: TouchTimes = config.parse_times()?;
+    let mode: Option<u32> = config.parse_mode()?;
+    let content: Option<String> = config.parse_content()?;
+    let mut record: Option<JournalRecord> = begin_journal(config.journal, "add")?;
 
     for d in v_dirs {
         for f in &v_files {
             let file_path = d.join(f);
-            match std::fs::write(file_path, "") {
-                Ok(_) => {}
+            let existing = fs::metadata(&file_path).ok();
+            if existing.is_none() && config.no_create {
+                continue;
+            }
+            if let (Some(r), Some(m)) = (&mut record, &existing) {
+                if let Err(err) = r.touched(&file_path, m) {
+                    error!(
+                        config.log,
+                        "Failed to record the file in the undo journal";
+                        "file" => f,
+                        "error" => %err
+                    );
+                    continue;
+                }
+            }
+            if existing.is_none() && config.parents {
+                if let Err(err) = create_parents(&file_path, &mut record) {
+                    error!(
+                        config.log,
+                        "Failed to create directories";
+                        "file" => f,
+                        "error" => %err
+                    );
+                    continue;
+                }
+            }
+
+            // like touch, never truncate an existing file
+            let res = match existing {
+                Some(_) => set_path_times(&file_path, &times),
+                None => match create_file(&file_path, &content, mode, &times) {
+                    Ok(size) => match &mut record {
+                        Some(r) => r.created(&file_path, size),
+                        None => Ok(()),
+                    },
+                    // it appeared since the check, so it is only touched
+                    Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                        fs::metadata(&file_path)
+                            .and_then(|m| match &mut record {
+                                Some(r) => r.touched(&file_path, &m),
+                                None => Ok(()),
+                            })
+                            .and_then(|_| set_path_times(&file_path, &times))
+                    }
+                    Err(err) => Err(err),
+                },
+            };
+            match res {
+                Ok(_) => {
+                    info!(config.log, "Added file"; "path" => %file_path.display());
+                }
+                Err(err) if existing.is_some() && err.kind() == io::ErrorKind::PermissionDenied => {
+                    error!(
+                        config.log,
+                        "Failed to update file times, this needs ownership or write permission";
+                        "file" => f,
+                        "error" => %err
+                    );
+                }
                 Err(err) => {
-                    eprintln!("Failed to add file {}: {}", f, err);
+                    error!(config.log, "Failed to add file"; "file" => f, "error" => %err);
                 }
             }
         }
     }
 
+    commit_journal(record, &config.log);
     Ok(())
 }
 
 pub struct AddConfig<'a> {
     pub dirs: Vec<&'a str>,
     pub files: Vec<&'a str>,
+    pub journal: bool,
+    /// Only change the access time (`-a`).
+    pub access_only: bool,
+    /// Only change the modification time (`-m`).
+    pub modify_only: bool,
+    pub no_create: bool,
+    pub date: Option<&'a str>,
+    pub reference: Option<&'a str>,
+    pub stamp: Option<&'a str>,
+    pub parents: bool,
+    /// Octal permissions for newly created files.
+    pub mode: Option<&'a str>,
+    /// File whose contents seed new files, see `fill_template`.
+    pub template: Option<&'a str>,
+    /// Text that seeds new files, see `fill_template`.
+    pub content: Option<&'a str>,
+    pub log: Logger,
 }
 
 impl<'a> AddConfig<'a> {
-    pub fn from_args(args: &'a ArgMatches) -> Self {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
         let dirs: Vec<&'a str> = args.values_of("dirs").unwrap().collect();
         let files: Vec<&'a str> = args.values_of("files").unwrap().collect();
+        let journal: bool = !args.is_present("no-journal");
+        let access_only: bool = args.is_present("a");
+        let modify_only: bool = args.is_present("m");
+        let no_create: bool = args.is_present("no-create");
+        let date: Option<&'a str> = args.value_of("date");
+        let reference: Option<&'a str> = args.value_of("reference");
+        let stamp: Option<&'a str> = args.value_of("stamp");
+        let parents: bool = args.is_present("parents");
+        let mode: Option<&'a str> = args.value_of("mode");
+        let template: Option<&'a str> = args.value_of("template");
+        let content: Option<&'a str> = args.value_of("content");
+
+        AddConfig {
+            dirs,
+            files,
+            journal,
+            access_only,
+            modify_only,
+            no_create,
+            date,
+            reference,
+            stamp,
+            parents,
+            mode,
+            template,
+            content,
+            log: log.new(o!("command" => "add")),
+        }
+    }
+
+    pub fn parse_mode(&self) -> Result<Option<u32>, &'static str> {
+        match self.mode {
+            Some(m) => match u32::from_str_radix(m, 8) {
+                Ok(mode) if mode <= 0o7777 => Ok(Some(mode)),
+                _ => Err("Invalid mode, expected octal permissions such as 644"),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// The unsubstituted text new files start with, if any.
+    pub fn parse_content(&self) -> Result<Option<String>, &'static str> {
+        if let Some(t) = self.template {
+            return fs::read_to_string(t)
+                .map(Some)
+                .map_err(|_| "Could not read the template file");
+        }
+        Ok(self.content.map(|c| c.to_string()))
+    }
+
+    /// The access and modification times to set, taken from `--reference`,
+    /// `--date`, `-t` or the current time, limited by `-a` and `-m`.
+    pub fn parse_times(&self) -> Result<TouchTimes, &'static str> {
+        let (accessed, modified) = if let Some(r) = self.reference {
+            let meta = fs::metadata(r).map_err(|_| "Could not read the reference file")?;
+            (
+                meta.accessed()
+                    .map_err(|_| "Could not read the reference file")?,
+                meta.modified()
+                    .map_err(|_| "Could not read the reference file")?,
+            )
+        } else if let Some(d) = self.date {
+            let t = parse_touch_date(d)?;
+            (t, t)
+        } else if let Some(t) = self.stamp {
+            let t = parse_touch_stamp(t)?;
+            (t, t)
+        } else {
+            let now = SystemTime::now();
+            (now, now)
+        };
 
-        AddConfig { dirs, files }
+        // -a and -m together are the same as neither
+        let both = self.access_only == self.modify_only;
+        Ok(TouchTimes {
+            accessed: (both || self.access_only).then_some(accessed),
+            modified: (both || self.modify_only).then_some(modified),
+        })
     }
 
     pub fn parse_dirs(&self) -> Result<Vec<PathBuf>, &'static str> {
@@ -52,7 +221,7 @@ impl<'a> AddConfig<'a> {
                 parsed = true;
                 res.push(dir);
             } else {
-                eprintln!("{} is an invalid directory or is inaccessible", d);
+                warn!(self.log, "Invalid or inaccessible directory, ignoring"; "dir" => d);
             }
         }
         if parsed {
@@ -62,50 +231,779 @@ impl<'a> AddConfig<'a> {
         }
     }
 
-    pub fn parse_files(&self) -> Result<Vec<&str>, &'static str> {
-        let mut res: Vec<&str> = Vec::new();
+    /// File names with bash-style brace expansion applied, see `expand_braces`.
+    pub fn parse_files(&self) -> Result<Vec<String>, &'static str> {
+        let mut res: Vec<String> = Vec::new();
 
         for f in &self.files {
-            res.push(*f);
+            res.extend(expand_braces(f)?);
         }
 
         Ok(res)
     }
 }
 
+/// Expand bash-style braces: lists (`{src,tests}/mod.rs`), numeric ranges with
+/// optional step and zero padding (`log_{01..31}.txt`, `{0..100..10}`) and
+/// character ranges (`{a..e}`). Groups can be nested or repeated; anything that
+/// isn't a valid brace expression is kept as is. More than 100000 names is an error.
+pub fn expand_braces(pattern: &str) -> Result<Vec<String>, &'static str> {
+    let bytes = pattern.as_bytes();
+    for (open, _) in pattern.match_indices('{') {
+        // find the matching close brace
+        let mut depth = 0;
+        let mut close = None;
+        for (i, &b) in bytes.iter().enumerate().skip(open) {
+            match b {
+                b'{' => depth += 1,
+                b'}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(i);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let close = match close {
+            Some(c) => c,
+            None => continue,
+        };
+
+        let inner = &pattern[open + 1..close];
+        let alternatives = split_top_level(inner);
+        let alternatives = if alternatives.len() > 1 {
+            alternatives
+        } else if let Some(seq) = brace_sequence(inner)? {
+            seq
+        } else {
+            continue;
+        };
+
+        let prefix = &pattern[..open];
+        let suffixes = expand_braces(&pattern[close + 1..])?;
+        let mut res: Vec<String> = Vec::new();
+        for alt in alternatives {
+            let expanded = expand_braces(&alt)?;
+            if (res.len() + expanded.len() * suffixes.len()) > MAX_BRACE_EXPANSION {
+                return Err(TOO_MANY_EXPANSIONS);
+            }
+            for a in expanded {
+                for suffix in &suffixes {
+                    res.push(format!("{}{}{}", prefix, a, suffix));
+                }
+            }
+        }
+        return Ok(res);
+    }
+    Ok(vec![pattern.to_string()])
+}
+
+/// Brace expansion gives up past this many names rather than exhaust memory.
+const MAX_BRACE_EXPANSION: usize = 100_000;
+const TOO_MANY_EXPANSIONS: &str = "Brace expansion gives more than 100000 names";
+
+/// Split on commas that aren't inside nested braces.
+fn split_top_level(inner: &str) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, b) in inner.bytes().enumerate() {
+        match b {
+            b'{' => depth += 1,
+            b'}' => depth -= 1,
+            b',' if depth == 0 => {
+                res.push(inner[start..i].to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    res.push(inner[start..].to_string());
+    res
+}
+
+/// Expand the inside of `{START..END[..STEP]}`, or `None` if it isn't a sequence.
+fn brace_sequence(inner: &str) -> Result<Option<Vec<String>>, &'static str> {
+    let parts: Vec<&str> = inner.split("..").collect();
+    if parts.len() != 2 && parts.len() != 3 {
+        return Ok(None);
+    }
+    let step: i64 = match parts.get(2).map(|s| s.parse::<i64>()) {
+        // i64::MIN has no absolute value, but a step that large only yields the start
+        Some(Ok(s)) => s.checked_abs().unwrap_or(i64::MAX).max(1),
+        Some(Err(_)) => return Ok(None),
+        None => 1,
+    };
+    // checked before anything is generated, {1..9223372036854775807} would never finish
+    let too_long = |start: i64, end: i64| {
+        (end as i128 - start as i128).unsigned_abs() / step as u128 >= MAX_BRACE_EXPANSION as u128
+    };
+
+    if let (Ok(start), Ok(end)) = (parts[0].parse::<i64>(), parts[1].parse::<i64>()) {
+        // a leading zero on either end pads everything to the widest end
+        let padded = |s: &str| {
+            let digits = s.trim_start_matches('-');
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = if padded(parts[0]) || padded(parts[1]) {
+            parts[0].len().max(parts[1].len())
+        } else {
+            0
+        };
+        if too_long(start, end) {
+            return Err(TOO_MANY_EXPANSIONS);
+        }
+        let values = range_with_step(start, end, step);
+        return Ok(Some(
+            values
+                .map(|n| {
+                    if n < 0 {
+                        format!("-{:0w$}", n.unsigned_abs(), w = width.saturating_sub(1))
+                    } else {
+                        format!("{:0w$}", n, w = width)
+                    }
+                })
+                .collect(),
+        ));
+    }
+
+    let single = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphanumeric() => Some(c as i64),
+            _ => None,
+        }
+    };
+    let (start, end) = match (single(parts[0]), single(parts[1])) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return Ok(None),
+    };
+    Ok(Some(
+        range_with_step(start, end, step)
+            .filter_map(|c| char::from_u32(c as u32).map(|c| c.to_string()))
+            .collect(),
+    ))
+}
+
+fn range_with_step(start: i64, end: i64, step: i64) -> Box<dyn Iterator<Item = i64>> {
+    if start <= end {
+        Box::new((start..=end).step_by(step as usize))
+    } else {
+        Box::new((end..=start).rev().step_by(step as usize))
+    }
+}
+
+/// The times `add` sets, `None` leaves that time alone.
+pub struct TouchTimes {
+    pub accessed: Option<SystemTime>,
+    pub modified: Option<SystemTime>,
+}
+
+/// Set the times of `path` without opening it, so files that can't be read
+/// can still be touched.
+fn set_path_times(path: &Path, times: &TouchTimes) -> io::Result<()> {
+    fn timespec(t: Option<SystemTime>) -> libc::timespec {
+        let (tv_sec, tv_nsec) = match t.map(|t| t.duration_since(UNIX_EPOCH)) {
+            None => (0, libc::UTIME_OMIT),
+            Some(Ok(d)) => (d.as_secs() as i64, d.subsec_nanos() as i64),
+            // before 1970, the nanoseconds still count forwards
+            Some(Err(e)) => {
+                let d = e.duration();
+                match d.subsec_nanos() {
+                    0 => (-(d.as_secs() as i64), 0),
+                    n => (-(d.as_secs() as i64) - 1, 1_000_000_000 - n as i64),
+                }
+            }
+        };
+        libc::timespec { tv_sec, tv_nsec }
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let spec = [timespec(times.accessed), timespec(times.modified)];
+    // SAFETY: the path is a valid NUL terminated string and spec holds two timespecs
+    let res = unsafe { libc::utimensat(libc::AT_FDCWD, c_path.as_ptr(), spec.as_ptr(), 0) };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Create `path` seeded with `content` and given `mode` and `times`. Fails with
+/// `AlreadyExists` if it exists. Returns the size of the new file.
+fn create_file(
+    path: &Path,
+    content: &Option<String>,
+    mode: Option<u32>,
+    times: &TouchTimes,
+) -> io::Result<u64> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    let mut size = 0;
+    if let Some(c) = content {
+        let filled = fill_template(c, path);
+        file.write_all(filled.as_bytes())?;
+        size = filled.len() as u64;
+    }
+    if let Some(m) = mode {
+        // set explicitly so the umask doesn't apply
+        file.set_permissions(fs::Permissions::from_mode(m))?;
+    }
+    let mut file_times = FileTimes::new();
+    if let Some(t) = times.accessed {
+        file_times = file_times.set_accessed(t);
+    }
+    if let Some(t) = times.modified {
+        file_times = file_times.set_modified(t);
+    }
+    file.set_times(file_times)?;
+    Ok(size)
+}
+
+/// Create the missing parent directories of `path`, outermost first, recording them for undo.
+fn create_parents(path: &Path, record: &mut Option<JournalRecord>) -> io::Result<()> {
+    let mut missing: Vec<&Path> = path
+        .ancestors()
+        .skip(1)
+        .take_while(|p| !p.as_os_str().is_empty() && !p.exists())
+        .collect();
+    missing.reverse();
+    for dir in missing {
+        fs::create_dir(dir)?;
+        if let Some(r) = record {
+            r.created(dir, 0)?;
+        }
+    }
+    Ok(())
+}
+
+/// Substitute `{{name}}` (the file name), `{{dir}}` (the name of the directory
+/// it is created in) and `{{date}}` (today, `YYYY-MM-DD`) in `template`.
+pub fn fill_template(template: &str, path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let dir = path
+        .parent()
+        .and_then(|p| fs::canonicalize(p).ok())
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+        .unwrap_or_default();
+    let date = Local::now().format("%Y-%m-%d").to_string();
+    template
+        .replace("{{name}}", &name)
+        .replace("{{dir}}", &dir)
+        .replace("{{date}}", &date)
+}
+
+/// Parse a `--date` string: `now`, `@SECONDS`, RFC 3339, or a local
+/// `YYYY-MM-DD` date optionally followed by `HH:MM[:SS]`.
+pub fn parse_touch_date(date: &str) -> Result<SystemTime, &'static str> {
+    let date = date.trim();
+    if date == "now" {
+        return Ok(SystemTime::now());
+    }
+    if let Some(secs) = date.strip_prefix('@') {
+        let secs: i64 = secs.parse().map_err(|_| "Invalid date")?;
+        let t = Local
+            .timestamp_opt(secs, 0)
+            .single()
+            .ok_or("Invalid date")?;
+        return Ok(t.into());
+    }
+    if let Ok(t) = DateTime::parse_from_rfc3339(date) {
+        return Ok(t.into());
+    }
+    for fmt in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(date, fmt) {
+            return local_time(naive);
+        }
+    }
+    if let Ok(day) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        return local_time(day.and_hms_opt(0, 0, 0).ok_or("Invalid date")?);
+    }
+    Err("Invalid date, expected now, @SECONDS, YYYY-MM-DD [HH:MM[:SS]] or RFC 3339")
+}
+
+/// Parse a `-t` stamp, `[[CC]YY]MMDDhhmm[.ss]` in local time like touch.
+pub fn parse_touch_stamp(stamp: &str) -> Result<SystemTime, &'static str> {
+    const INVALID: &str = "Invalid time stamp, expected [[CC]YY]MMDDhhmm[.ss]";
+    let (main, secs) = match stamp.split_once('.') {
+        Some((m, s)) if s.len() == 2 => (m, s.parse::<u32>().map_err(|_| INVALID)?),
+        Some(_) => return Err(INVALID),
+        None => (stamp, 0),
+    };
+    if !main.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(INVALID);
+    }
+    let num = |s: &str| s.parse::<u32>().map_err(|_| INVALID);
+    let (year, rest) = match main.len() {
+        8 => (Local::now().year(), main),
+        // two digit years follow POSIX: 69-99 are 19xx, 00-68 are 20xx
+        10 => {
+            let yy = num(&main[..2])? as i32;
+            (if yy >= 69 { 1900 + yy } else { 2000 + yy }, &main[2..])
+        }
+        12 => (num(&main[..4])? as i32, &main[4..]),
+        _ => return Err(INVALID),
+    };
+    let naive = NaiveDate::from_ymd_opt(year, num(&rest[..2])?, num(&rest[2..4])?)
+        .and_then(|d| d.and_hms_opt(num(&rest[4..6]).ok()?, num(&rest[6..8]).ok()?, secs))
+        .ok_or(INVALID)?;
+    local_time(naive)
+}
+
+fn local_time(naive: NaiveDateTime) -> Result<SystemTime, &'static str> {
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(SystemTime::from)
+        .ok_or("Time does not exist in the local time zone")
+}
+
 /**************************** rust_add ends **************************** */
 
 /**************************** rust_remove starts **************************** */
 pub struct RemoveConfig<'a> {
     pub dirs: Vec<&'a str>,
     pub files: Vec<&'a str>,
+    pub patterns: Vec<&'a str>,
+    pub recursive: bool,
+    pub force: bool,
+    pub journal: bool,
+    pub trash: bool,
+    pub dry_run: bool,
+    pub interactive: bool,
+    pub interactive_once: bool,
+    /// `-I` only asks when more than this many items are removed, defaults to
+    /// `interactive_threshold` from the config.
+    pub threshold: Option<&'a str>,
+    /// Number of random overwrite passes before deleting, see `shred_file`.
+    pub shred: Option<&'a str>,
+    pub log: Logger,
 }
 
 pub fn run_remove(config: &RemoveConfig) -> Result<(), &'static str> {
+    run_remove_with(config, &mut StdinPrompt)
+}
+
+/// `run_remove` with the yes/no questions of `-i` and `-I` answered by `prompt`.
+pub fn run_remove_with(config: &RemoveConfig, prompt: &mut dyn Prompt) -> Result<(), &'static str> {
+    let threshold: usize = config.parse_threshold()?;
+    let shred: Option<usize> = config.parse_shred()?;
     let v_dirs: Vec<PathBuf> = config.parse_dirs()?;
-    let v_files: Vec<&str> = config.parse_files()?;
+    let v_targets: Vec<PathBuf> = config.parse_targets(&v_dirs)?;
 
-    for d in v_dirs {
-        for f in &v_files {
-            let file_path = d.join(f);
-            match std::fs::remove_file(file_path) {
-                Ok(_) => {}
-                Err(err) => {
-                    eprintln!("Failed to remove file {}: {}", f, err);
+    // weed out what can't be removed before asking or reporting anything
+    let mut v_remove: Vec<(PathBuf, fs::Metadata)> = Vec::new();
+    for file_path in v_targets {
+        let meta = match fs::symlink_metadata(&file_path) {
+            Ok(m) => m,
+            Err(err) => {
+                if !config.force {
+                    error!(
+                        config.log,
+                        "Failed to remove file";
+                        "path" => %file_path.display(),
+                        "error" => %err
+                    );
+                }
+                continue;
+            }
+        };
+        if meta.is_dir() {
+            if !config.recursive {
+                warn!(
+                    config.log,
+                    "Not removing a directory without -r";
+                    "path" => %file_path.display()
+                );
+                continue;
+            }
+            if is_protected_path(&file_path) {
+                warn!(
+                    config.log,
+                    "Refusing to remove a protected directory";
+                    "path" => %file_path.display()
+                );
+                continue;
+            }
+        }
+        v_remove.push((file_path, meta));
+    }
+
+    if config.dry_run {
+        let mut total = 0;
+        for (file_path, _) in &v_remove {
+            let size = path_size(file_path);
+            total += size;
+            println!(
+                "would remove {} ({})",
+                file_path.display(),
+                human_size(size)
+            );
+        }
+        println!("{} item(s), {} in total", v_remove.len(), human_size(total));
+        return Ok(());
+    }
+
+    if config.interactive_once
+        && (v_remove.len() > threshold || v_remove.iter().any(|(_, m)| m.is_dir()))
+        && !prompt.confirm(&format!("remove {} item(s)?", v_remove.len()))
+    {
+        return Ok(());
+    }
+
+    if let Some(passes) = shred {
+        return shred_all(&v_remove, passes, config, prompt);
+    }
+
+    let mut record: Option<JournalRecord> = begin_journal(config.journal, "remove")?;
+    let trash: Option<Trash> = if config.trash {
+        Some(Trash::open().map_err(|_| "Could not open the trash")?)
+    } else {
+        None
+    };
+
+    let max_size = record
+        .as_ref()
+        .map_or(u64::MAX, |_| Settings::load(&config.log).journal_max_size);
+
+    for (file_path, meta) in v_remove {
+        if config.interactive && !prompt.confirm(&format!("remove {}?", file_path.display())) {
+            continue;
+        }
+
+        // from another filesystem the journal gets a full copy, which isn't
+        // worth making when it would be pruned right away
+        let mut journal = record.as_mut();
+        if trash.is_none()
+            && journal.as_ref().is_some_and(|r| !r.same_filesystem(&meta))
+            && path_size(&file_path) > max_size
+        {
+            warn!(
+                config.log,
+                "Too large to copy into the undo journal from another filesystem, removing it for good";
+                "path" => %file_path.display()
+            );
+            journal = None;
+        }
+
+        // with a journal the file is moved aside instead of deleted so it can be restored
+        let res = match (&trash, journal) {
+            (Some(t), Some(r)) => t.put(&file_path, &config.log).map(|item| r.trashed(&item)),
+            (Some(t), None) => t.put(&file_path, &config.log).map(|_| ()),
+            (None, Some(r)) => r.move_aside(&file_path, &config.log),
+            (None, None) if meta.is_dir() => fs::remove_dir_all(&file_path),
+            (None, None) => fs::remove_file(&file_path),
+        };
+        match res {
+            Ok(_) => {
+                info!(config.log, "Removed"; "path" => %file_path.display());
+            }
+            Err(err) => {
+                error!(
+                    config.log,
+                    "Failed to remove file";
+                    "path" => %file_path.display(),
+                    "error" => %err
+                );
+            }
+        }
+    }
+
+    commit_journal(record, &config.log);
+    Ok(())
+}
+
+fn shred_all(
+    v_remove: &[(PathBuf, fs::Metadata)],
+    passes: usize,
+    config: &RemoveConfig,
+    prompt: &mut dyn Prompt,
+) -> Result<(), &'static str> {
+    warn!(
+        config.log,
+        "Shredding only overwrites data in place. Copy-on-write and log-structured \
+         filesystems (btrfs, ZFS, ...), snapshots, backups and SSD wear levelling may keep \
+         old copies of the contents."
+    );
+    let mut warned: Vec<String> = Vec::new();
+
+    for (file_path, meta) in v_remove {
+        if config.interactive && !prompt.confirm(&format!("shred {}?", file_path.display())) {
+            continue;
+        }
+        if let Some(fs_type) = cow_filesystem(file_path) {
+            if !warned.contains(&fs_type) {
+                warn!(
+                    config.log,
+                    "Copy-on-write filesystem, the old contents may survive shredding";
+                    "path" => %file_path.display(),
+                    "filesystem" => &fs_type
+                );
+                warned.push(fs_type);
+            }
+        }
+
+        let res = if meta.is_dir() {
+            shred_dir(file_path, passes, &config.log)
+        } else {
+            shred_file(file_path, passes, &config.log)
+        };
+        match res {
+            Ok(_) => info!(config.log, "Shredded"; "path" => %file_path.display()),
+            Err(err) => {
+                error!(
+                    config.log,
+                    "Failed to shred";
+                    "path" => %file_path.display(),
+                    "error" => %err
+                )
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn shred_dir(dir: &Path, passes: usize, log: &Logger) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            shred_dir(&entry.path(), passes, log)?;
+        } else {
+            shred_file(&entry.path(), passes, log)?;
+        }
+    }
+    fs::remove_dir(dir)
+}
+
+/// Overwrite `path` with `passes` rounds of random data and a final round of
+/// zeros, syncing after each, then rename it a few times to hide its name and
+/// unlink it. Symlinks are removed without touching what they point to.
+pub fn shred_file(path: &Path, passes: usize, log: &Logger) -> io::Result<()> {
+    let meta = fs::symlink_metadata(path)?;
+    let mut urandom = File::open("/dev/urandom")?;
+
+    if meta.is_file() {
+        if meta.nlink() > 1 {
+            warn!(
+                log,
+                "File has other hard links, they will see the overwritten contents";
+                "path" => %path.display()
+            );
+        }
+        let mut f = OpenOptions::new().write(true).open(path)?;
+        let mut buf = vec![0u8; 64 * 1024];
+        for pass in 0..=passes {
+            f.seek(SeekFrom::Start(0))?;
+            let zeros = pass == passes;
+            let mut left = meta.len();
+            while left > 0 {
+                let n = left.min(buf.len() as u64) as usize;
+                if zeros {
+                    buf[..n].fill(0);
+                } else {
+                    urandom.read_exact(&mut buf[..n])?;
                 }
+                f.write_all(&buf[..n])?;
+                left -= n as u64;
             }
+            f.sync_all()?;
         }
     }
 
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name_len = path.file_name().map(|n| n.len()).unwrap_or(8).clamp(1, 32);
+    let mut current = path.to_path_buf();
+    for _ in 0..3 {
+        let mut bytes = vec![0u8; name_len];
+        urandom.read_exact(&mut bytes)?;
+        let name: String = bytes
+            .iter()
+            .map(|b| char::from(b"abcdefghijklmnopqrstuvwxyz0123456789"[*b as usize % 36]))
+            .collect();
+        let next = dir.join(name);
+        if fs::symlink_metadata(&next).is_err() {
+            fs::rename(&current, &next)?;
+            current = next;
+        }
+    }
+    fs::remove_file(&current)?;
+    if let Ok(d) = File::open(&dir) {
+        let _ = d.sync_all();
+    }
     Ok(())
 }
 
+/// The type of the filesystem holding `path` if it is known to be copy-on-write,
+/// looked up in `/proc/mounts`.
+fn cow_filesystem(path: &Path) -> Option<String> {
+    let path = fs::canonicalize(path).ok()?;
+    let mounts = fs::read_to_string("/proc/mounts").ok()?;
+    let fs_type = mounts
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let mount_point = parts.nth(1)?.replace("\\040", " ");
+            let fs_type = parts.next()?;
+            Some((PathBuf::from(mount_point), fs_type.to_string()))
+        })
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.as_os_str().len())?
+        .1;
+    match fs_type.as_str() {
+        "btrfs" | "zfs" | "bcachefs" | "nilfs2" | "f2fs" | "overlay" => Some(fs_type),
+        _ => None,
+    }
+}
+
+/// Asks the user yes/no questions, so tests can answer without a terminal.
+pub trait Prompt {
+    fn confirm(&mut self, question: &str) -> bool;
+}
+
+/// Prompts on stderr and reads the answer from stdin. Anything but `y`/`yes` is a no.
+pub struct StdinPrompt;
+
+impl Prompt for StdinPrompt {
+    fn confirm(&mut self, question: &str) -> bool {
+        eprint!("{} [y/N] ", question);
+        let _ = io::stderr().flush();
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer).is_err() {
+            return false;
+        }
+        matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+    }
+}
+
+/// Apparent size of `path`, including everything below it if it is a directory.
+pub fn path_size(path: &Path) -> u64 {
+    match fs::symlink_metadata(path) {
+        Ok(m) if m.is_dir() => fs::read_dir(path)
+            .map(|rd| rd.flatten().map(|e| path_size(&e.path())).sum())
+            .unwrap_or(0),
+        Ok(m) => m.len(),
+        Err(_) => 0,
+    }
+}
+
+/// Format a byte count with binary units, e.g. `1.5 KiB`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
 impl<'a> RemoveConfig<'a> {
-    pub fn from_args(args: &'a ArgMatches) -> Self {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
         let dirs: Vec<&'a str> = args.values_of("dirs").unwrap().collect();
-        let files: Vec<&'a str> = args.values_of("files").unwrap().collect();
+        let mut files: Vec<&'a str> = Vec::new();
+        if let Some(val) = args.values_of("files") {
+            files = val.collect();
+        }
+        let mut patterns: Vec<&'a str> = Vec::new();
+        if let Some(val) = args.values_of("pattern") {
+            patterns = val.collect();
+        }
+        let recursive: bool = args.is_present("recursive");
+        let force: bool = args.is_present("force");
+        let journal: bool = !args.is_present("no-journal");
+        let settings = Settings::load(log);
+        let trash: bool =
+            args.is_present("trash") || (settings.trash && !args.is_present("no-trash"));
+        let dry_run: bool = args.is_present("dry-run");
+        let interactive: bool = args.is_present("interactive");
+        let interactive_once: bool = args.is_present("interactive-once");
+        let threshold: Option<&'a str> = args.value_of("interactive-threshold");
+        let shred: Option<&'a str> = args.value_of("shred");
+
+        RemoveConfig {
+            dirs,
+            files,
+            patterns,
+            recursive,
+            force,
+            journal,
+            trash,
+            dry_run,
+            interactive,
+            interactive_once,
+            threshold,
+            shred,
+            log: log.new(o!("command" => "remove")),
+        }
+    }
+
+    pub fn parse_threshold(&self) -> Result<usize, &'static str> {
+        match self.threshold {
+            Some(t) => t
+                .parse()
+                .map_err(|_| "Invalid --interactive-threshold, expected a number"),
+            None => Ok(Settings::load(&self.log).interactive_threshold),
+        }
+    }
+
+    pub fn parse_shred(&self) -> Result<Option<usize>, &'static str> {
+        match self.shred {
+            Some(p) => match p.parse() {
+                Ok(n) if n > 0 => Ok(Some(n)),
+                _ => Err("Invalid --shred, expected a positive number of passes"),
+            },
+            None => Ok(None),
+        }
+    }
 
-        RemoveConfig { dirs, files }
+    /// Everything to remove: the named files in each directory, followed by the
+    /// files matching `--pattern` (searched recursively with `-r`).
+    pub fn parse_targets(&self, dirs: &[PathBuf]) -> Result<Vec<PathBuf>, &'static str> {
+        let v_files: Vec<String> = self.parse_files()?;
+        let v_pats: Vec<Regex> = if self.patterns.is_empty() {
+            Vec::new()
+        } else {
+            parse_regexes(&self.patterns, &self.log)?
+        };
+
+        let mut res: Vec<PathBuf> = Vec::new();
+        for d in dirs {
+            for f in &v_files {
+                res.push(d.join(f));
+            }
+            if v_pats.is_empty() {
+                continue;
+            }
+            let mut matched: Vec<MyFile> = Vec::new();
+            if self.recursive {
+                get_matched_files(&mut matched, d, &v_pats, None, false, &self.log);
+            } else if let Ok(readdir) = fs::read_dir(d) {
+                for entry in readdir.flatten() {
+                    let path = entry.path();
+                    if path.is_file() {
+                        if let Ok(file) = MyFile::from_path(&path) {
+                            if v_pats.iter().any(|rgx| rgx.is_match(&file.path)) {
+                                matched.push(file);
+                            }
+                        }
+                    }
+                }
+            }
+            res.extend(matched.into_iter().map(|f| PathBuf::from(f.path)));
+        }
+        Ok(res)
     }
 
     pub fn parse_dirs(&self) -> Result<Vec<PathBuf>, &'static str> {
@@ -117,7 +1015,7 @@ impl<'a> RemoveConfig<'a> {
                 parsed = true;
                 res.push(dir);
             } else {
-                eprintln!("{} is an invalid directory or is inaccessible", d);
+                warn!(self.log, "Invalid or inaccessible directory, ignoring"; "dir" => d);
             }
         }
         if parsed {
@@ -127,17 +1025,52 @@ impl<'a> RemoveConfig<'a> {
         }
     }
 
-    pub fn parse_files(&self) -> Result<Vec<&str>, &'static str> {
-        let mut res: Vec<&str> = Vec::new();
+    /// File names with bash-style brace expansion applied, see `expand_braces`.
+    pub fn parse_files(&self) -> Result<Vec<String>, &'static str> {
+        let mut res: Vec<String> = Vec::new();
 
         for f in &self.files {
-            res.push(*f);
+            res.extend(expand_braces(f)?);
         }
 
         Ok(res)
     }
 }
 
+/// Whether removing `path` would take out `/`, the home directory or the
+/// current directory. The last component isn't resolved, so a symlink to one
+/// of them can still be removed.
+pub fn is_protected_path(path: &Path) -> bool {
+    let target = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            fs::canonicalize(parent).map(|p| p.join(name))
+        }
+        _ => fs::canonicalize(path),
+    };
+    let target = match target {
+        Ok(t) => t,
+        Err(_) => return false,
+    };
+
+    // covers the current directory and everything above it, including `/`
+    if let Ok(cwd) = std::env::current_dir().and_then(fs::canonicalize) {
+        if cwd.starts_with(&target) {
+            return true;
+        }
+    }
+    if let Ok(home) = home_dir().and_then(fs::canonicalize) {
+        if home == target {
+            return true;
+        }
+    }
+    target == Path::new("/")
+}
+
 /**************************** rust_remove ends **************************** */
 
 /**************************** rust_find starts **************************** */
@@ -153,44 +1086,74 @@ pub fn run_find(config: &FindConfig) -> Result<(), &'static str> {
 
     let size: Option<u64> = config.parse_size();
 
+    let colors: Colors = parse_color(config.color)?;
+
     // 4. get files and output
+    find_once(config, &v_pats, &v_dirs, size, &mut output, &colors);
+
+    // 5. and again whenever a matching file changes
+    if config.watch {
+        let debounce = parse_debounce(config.debounce)?;
+        let mut watcher =
+            Watcher::new(&config.log).map_err(|_| "Could not start watching for changes")?;
+        for dir in &v_dirs {
+            watcher
+                .add_tree(dir)
+                .map_err(|_| "Could not watch the directories")?;
+        }
+        // writing the results must not trigger another search
+        if let Some(o) = config.output {
+            watcher.ignore(Path::new(o));
+        }
+        loop {
+            let changes = watcher
+                .wait(debounce)
+                .map_err(|_| "Watching for changes failed")?;
+            if changes.iter().any(|c| c.matches(&v_pats)) {
+                // the new results replace the previous ones
+                if let Some(o) = &mut output {
+                    if let Err(err) = o.set_len(0).and_then(|_| o.rewind()) {
+                        warn!(config.log, "Could not clear the output file"; "error" => %err);
+                    }
+                }
+                find_once(config, &v_pats, &v_dirs, size, &mut output, &colors);
+                if config.exec.is_some() {
+                    // what the command wrote itself
+                    watcher.drain().map_err(|_| "Watching for changes failed")?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn find_once(
+    config: &FindConfig,
+    v_pats: &[Regex],
+    v_dirs: &[PathBuf],
+    size: Option<u64>,
+    output: &mut Option<File>,
+    colors: &Colors,
+) {
     let mut matched_files = Vec::with_capacity(v_dirs.len());
     for dir in v_dirs.iter() {
-        get_matched_files(&mut matched_files, dir, &v_pats[..], size);
+        get_matched_files(
+            &mut matched_files,
+            dir,
+            v_pats,
+            size,
+            config.archives,
+            &config.log,
+        );
 
-        if let Some(sv) = display(&matched_files, &mut output) {
+        if let Some(sv) = display(&matched_files, output, colors) {
             // string implements clone
             let paths = matched_files
                 .iter()
                 .map(|x| x.path.clone())
                 .collect::<Vec<_>>();
             if let Some(exec) = config.exec {
-                // split strings in accordance with shell expansion
-                let cmd = split(exec).unwrap();
-                // find the index of the replace string
-                // the replace string technically does not have to be there
-                let pos = cmd
-                    .iter()
-                    .position(|x| x == config.replace.unwrap())
-                    .unwrap();
-                if config.all {
-                    let cmd = [&cmd[..pos], &paths, &cmd[(pos + 1)..]].concat();
-                    // run command with all files as args
-                    Command::new(&cmd[0])
-                        .args(&cmd[1..])
-                        .spawn()
-                        .expect("failed to run process");
-                } else {
-                    // run 1 command per each file
-                    // TODO: specify threads
-                    for path in paths {
-                        let cmd = [&cmd[..pos], &[path], &cmd[(pos + 1)..]].concat();
-                        Command::new(&cmd[0])
-                            .args(&cmd[1..])
-                            .spawn()
-                            .expect("failed to run process");
-                    }
-                }
+                run_exec(exec, config.replace, &paths, config.all, &config.log);
             } else {
                 for s in sv {
                     println!("{}", s);
@@ -199,22 +1162,75 @@ pub fn run_find(config: &FindConfig) -> Result<(), &'static str> {
         };
         matched_files.clear();
     }
-    Ok(())
 }
 
-pub struct FindConfig<'a> {
-    pub dirs: Vec<&'a str>,
-    pub patterns: Vec<&'a str>,
-    pub output: Option<&'a str>,
-    pub size: Option<&'a str>,
-    pub exec: Option<&'a str>,
-    pub replace: Option<&'a str>,
-    pub all: bool,
+/// Run `exec` with the first occurence of `replace` swapped for the paths, all
+/// at once if `all` is set or once per path otherwise. Without a replace
+/// string in the command it runs once as it is.
+fn run_exec(exec: &str, replace: Option<&str>, paths: &[String], all: bool, log: &Logger) {
+    // split strings in accordance with shell expansion
+    let cmd = match split(exec) {
+        Some(cmd) if !cmd.is_empty() => cmd,
+        _ => {
+            error!(log, "Could not parse the command"; "exec" => exec);
+            return;
+        }
+    };
+    // find the index of the replace string
+    let pos = replace.and_then(|r| cmd.iter().position(|x| x == r));
+    let runs: Vec<Vec<String>> = match pos {
+        None => vec![cmd],
+        // run command with all files as args
+        Some(pos) if all => vec![[&cmd[..pos], paths, &cmd[(pos + 1)..]].concat()],
+        // run 1 command per each file
+        // TODO: specify threads
+        Some(pos) => paths
+            .iter()
+            .map(|path| [&cmd[..pos], std::slice::from_ref(path), &cmd[(pos + 1)..]].concat())
+            .collect(),
+    };
+    for cmd in runs {
+        debug!(log, "Running command"; "argv" => ?cmd);
+        match Command::new(&cmd[0]).args(&cmd[1..]).spawn() {
+            Ok(mut child) => match child.wait() {
+                Ok(status) if !status.success() => {
+                    warn!(log, "Command failed"; "program" => &cmd[0], "status" => %status);
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    error!(
+                        log,
+                        "Failed to wait on the command";
+                        "program" => &cmd[0],
+                        "error" => %err
+                    );
+                }
+            },
+            Err(err) => {
+                error!(log, "Failed to run the command"; "program" => &cmd[0], "error" => %err)
+            }
+        }
+    }
+}
+
+pub struct FindConfig<'a> {
+    pub dirs: Vec<&'a str>,
+    pub patterns: Vec<&'a str>,
+    pub output: Option<&'a str>,
+    pub size: Option<&'a str>,
+    pub exec: Option<&'a str>,
+    pub replace: Option<&'a str>,
+    pub all: bool,
+    pub watch: bool,
+    pub debounce: Option<&'a str>,
+    pub archives: bool,
+    pub color: Option<&'a str>,
+    pub log: Logger,
 }
 
 impl<'a> FindConfig<'a> {
     // you need to use explit lifetime here as well
-    pub fn from_args(args: &'a ArgMatches) -> Self {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
         let patterns: Vec<&'a str> = args.values_of("patterns").unwrap().collect();
         let dirs: Vec<&'a str> = args.values_of("dirs").unwrap().collect();
         let output: Option<&'a str> = args.value_of("output");
@@ -222,6 +1238,10 @@ impl<'a> FindConfig<'a> {
         let exec: Option<&'a str> = args.value_of("exec");
         let replace: Option<&'a str> = args.value_of("replace");
         let all: bool = args.is_present("all");
+        let watch: bool = args.is_present("watch");
+        let debounce: Option<&'a str> = args.value_of("debounce");
+        let archives: bool = args.is_present("archives");
+        let color: Option<&'a str> = args.value_of("color");
 
         FindConfig {
             patterns,
@@ -231,25 +1251,16 @@ impl<'a> FindConfig<'a> {
             exec,
             replace,
             all,
+            watch,
+            debounce,
+            archives,
+            color,
+            log: log.new(o!("command" => "find")),
         }
     }
 
     pub fn parse_patterns(&self) -> Result<Vec<Regex>, &'static str> {
-        let mut res: Vec<Regex> = Vec::new();
-        let mut parsed = false;
-        for p in &self.patterns {
-            if let Ok(rgx) = Regex::new(p) {
-                res.push(rgx);
-                parsed = true;
-            } else {
-                eprintln!("{} is not a valid regular expression, ignoring", p);
-            }
-        }
-        if parsed {
-            Ok(res)
-        } else {
-            Err("No valid regex given")
-        }
+        parse_regexes(&self.patterns, &self.log)
     }
 
     pub fn parse_dirs(&self) -> Result<Vec<PathBuf>, &'static str> {
@@ -261,7 +1272,7 @@ impl<'a> FindConfig<'a> {
                 parsed = true;
                 res.push(dir);
             } else {
-                eprintln!("{} is an invalid directory or is inaccessible", d);
+                warn!(self.log, "Invalid or inaccessible directory, ignoring"; "dir" => d);
             }
         }
         if parsed {
@@ -277,7 +1288,11 @@ impl<'a> FindConfig<'a> {
             if let Ok(file) = File::create(f) {
                 return Some(file);
             } else {
-                eprintln!("Couldn't open {} for writing, not writing to file", f);
+                warn!(
+                    self.log,
+                    "Could not open the output file, writing to stdout instead";
+                    "output" => f
+                );
             }
         }
         None
@@ -302,25 +1317,122 @@ impl<'a> FindConfig<'a> {
     }
 }
 
-pub fn get_matched_files(files: &mut Vec<MyFile>, dir: &Path, pats: &[Regex], size: Option<u64>) {
+/// Compile `patterns`, skipping invalid ones. Fails if none of them are valid.
+pub fn parse_regexes(patterns: &[&str], log: &Logger) -> Result<Vec<Regex>, &'static str> {
+    let mut res: Vec<Regex> = Vec::new();
+    let mut parsed = false;
+    for p in patterns {
+        match Regex::new(p) {
+            Ok(rgx) => {
+                res.push(rgx);
+                parsed = true;
+            }
+            Err(err) => warn!(
+                log,
+                "Invalid regular expression, ignoring";
+                "pattern" => p,
+                "error" => regex_error_line(&err)
+            ),
+        }
+    }
+    if parsed {
+        Ok(res)
+    } else {
+        Err("No valid regex given")
+    }
+}
+
+/// The gist of a regex error on one line. Parse errors repeat the pattern with
+/// a caret under the problem over several lines, the last one says what's wrong.
+pub fn regex_error_line(err: &regex::Error) -> String {
+    let msg = err.to_string();
+    msg.lines()
+        .rev()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .unwrap_or_default()
+        .to_string()
+}
+
+pub fn get_matched_files(
+    files: &mut Vec<MyFile>,
+    dir: &Path,
+    pats: &[Regex],
+    size: Option<u64>,
+    archives: bool,
+    log: &Logger,
+) {
     // call get_matched_files() in itself if the given directory `dir` contains a sub-directory
-    if let Ok(readdir) = fs::read_dir(dir) {
-        for entry in readdir.flatten() {
-            let path = entry.path();
-            if path.is_dir() {
-                get_matched_files(files, &path, pats, size);
-            } else if path.is_file() {
-                if let Ok(file) = MyFile::from_path(&path) {
-                    if size.is_none() || file.size_bytes > size.unwrap() {
-                        for rgx in pats {
-                            if rgx.is_match(&file.path) {
-                                files.push(file);
-                                break;
-                            }
+    let readdir = match fs::read_dir(dir) {
+        Ok(r) => r,
+        Err(err) => {
+            warn!(
+                log,
+                "Could not read directory, skipping";
+                "dir" => %dir.display(),
+                "error" => %err
+            );
+            return;
+        }
+    };
+    trace!(log, "Searching directory"; "dir" => %dir.display());
+    for entry in readdir.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            get_matched_files(files, &path, pats, size, archives, log);
+        } else if path.is_file() {
+            if let Ok(file) = MyFile::from_path(&path) {
+                if size.is_none() || file.size_bytes > size.unwrap() {
+                    for rgx in pats {
+                        if rgx.is_match(&file.path) {
+                            files.push(file);
+                            break;
                         }
                     }
                 }
             }
+            if archives {
+                get_matched_archive_entries(files, &path, pats, size, log);
+            }
+        }
+    }
+}
+
+/// Add the files inside `archive` that match, as virtual files whose path is
+/// `archive!/member` (see `archive_member_path`). Nothing is extracted.
+fn get_matched_archive_entries(
+    files: &mut Vec<MyFile>,
+    archive: &Path,
+    pats: &[Regex],
+    size: Option<u64>,
+    log: &Logger,
+) {
+    let format = match ArchiveFormat::from_path(archive) {
+        Some(f) => f,
+        None => return,
+    };
+    let entries = match list_archive(archive, format) {
+        Ok(e) => e,
+        Err(err) => {
+            warn!(
+                log,
+                "Could not read the archive, skipping";
+                "archive" => %archive.display(),
+                "error" => %err
+            );
+            return;
+        }
+    };
+    for mut file in entries {
+        if file.is_dir() || file.is_symlink() {
+            continue;
+        }
+        if size.is_some() && file.size_bytes <= size.unwrap() {
+            continue;
+        }
+        file.path = archive_member_path(archive, &file.path);
+        if pats.iter().any(|rgx| rgx.is_match(&file.path)) {
+            files.push(file);
         }
     }
 }
@@ -330,44 +1442,159 @@ pub struct MyFile {
     pub name: String,
     pub path: String,
     pub size_bytes: u64,
+    /// File type and permission bits, as in `st_mode`.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub modified: SystemTime,
+    /// Allocated 512-byte blocks, smaller than the size for sparse files.
+    pub blocks: u64,
+    pub dev: u64,
+    pub ino: u64,
+    pub nlink: u64,
 }
 
 impl MyFile {
     /// Instantiate a MyFile struct from the path of a file.
     pub fn from_path(path: &Path) -> Result<Self, &'static str> {
-        let name = path
-            .file_name()
-            .ok_or("Couldn't parse filename")?
-            .to_str()
-            .ok_or("Failed conversion from osstr to str")?
-            .to_string();
-        let pathstr = path
-            .to_str()
-            .ok_or("Could not convert path to str")?
-            .to_string();
-        let meta = path.metadata();
-        let size_bytes = match meta {
-            Ok(m) => m.len(),
+        Self::from_metadata(path, path.metadata(), false)
+    }
+
+    /// Like `from_path`, but describes a symlink itself rather than what it
+    /// points to. Names that aren't UTF-8 are kept with U+FFFD in place of the
+    /// invalid bytes, so listings still show them.
+    pub fn from_path_no_follow(path: &Path) -> Result<Self, &'static str> {
+        Self::from_metadata(path, fs::symlink_metadata(path), true)
+    }
+
+    fn from_metadata(
+        path: &Path,
+        meta: io::Result<fs::Metadata>,
+        lossy: bool,
+    ) -> Result<Self, &'static str> {
+        let file_name = path.file_name().ok_or("Couldn't parse filename")?;
+        let (name, pathstr) = if lossy {
+            (
+                file_name.to_string_lossy().into_owned(),
+                path.to_string_lossy().into_owned(),
+            )
+        } else {
+            (
+                file_name
+                    .to_str()
+                    .ok_or("Failed conversion from osstr to str")?
+                    .to_string(),
+                path.to_str()
+                    .ok_or("Could not convert path to str")?
+                    .to_string(),
+            )
+        };
+        let meta = match meta {
+            Ok(m) => m,
             Err(_) => return Err("Could not fetch metadata"),
         };
         let myfile = MyFile {
             name,
             path: pathstr,
-            size_bytes,
+            size_bytes: meta.len(),
+            mode: meta.mode(),
+            uid: meta.uid(),
+            gid: meta.gid(),
+            modified: meta.modified().unwrap_or(UNIX_EPOCH),
+            blocks: meta.blocks(),
+            dev: meta.dev(),
+            ino: meta.ino(),
+            nlink: meta.nlink(),
         };
         Ok(myfile)
     }
+
+    /// Describe a member of an archive, `path` being its name inside it.
+    pub fn from_archive_entry(
+        path: &str,
+        size_bytes: u64,
+        mode: u32,
+        modified: SystemTime,
+    ) -> Self {
+        let name = Path::new(path)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string());
+        MyFile {
+            name,
+            path: path.to_string(),
+            size_bytes,
+            mode,
+            uid: 0,
+            gid: 0,
+            modified,
+            blocks: 0,
+            dev: 0,
+            ino: 0,
+            nlink: 1,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & libc::S_IFMT == libc::S_IFDIR
+    }
+
+    /// Digest of the file's contents, see `hash_file`.
+    pub fn content_hash(&self, algorithm: HashAlgorithm) -> io::Result<String> {
+        hash_file(Path::new(&self.path), algorithm)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & libc::S_IFMT == libc::S_IFLNK
+    }
+
+    /// The mode as `ls -l` shows it, e.g. `drwxr-xr-x`.
+    pub fn mode_string(&self) -> String {
+        let kind = match self.mode & libc::S_IFMT {
+            libc::S_IFDIR => 'd',
+            libc::S_IFLNK => 'l',
+            libc::S_IFCHR => 'c',
+            libc::S_IFBLK => 'b',
+            libc::S_IFIFO => 'p',
+            libc::S_IFSOCK => 's',
+            _ => '-',
+        };
+        let mut res = String::with_capacity(10);
+        res.push(kind);
+        // (read, write, execute, special bit, special char)
+        let triplets = [
+            (0o400, 0o200, 0o100, libc::S_ISUID, 's'),
+            (0o040, 0o020, 0o010, libc::S_ISGID, 's'),
+            (0o004, 0o002, 0o001, libc::S_ISVTX, 't'),
+        ];
+        for (r, w, x, special, c) in triplets {
+            res.push(if self.mode & r != 0 { 'r' } else { '-' });
+            res.push(if self.mode & w != 0 { 'w' } else { '-' });
+            res.push(match (self.mode & x != 0, self.mode & special != 0) {
+                (true, true) => c,
+                (false, true) => c.to_ascii_uppercase(),
+                (true, false) => 'x',
+                (false, false) => '-',
+            });
+        }
+        res
+    }
 }
 
-pub fn display(files: &[MyFile], output: &mut Option<File>) -> Option<Vec<String>> {
-    let res: Vec<String> = files.iter().map(|f| f.path.to_string()).collect();
+/// The paths of `files`, written to `output` if given or else returned
+/// colored by file type. Files never get colors.
+pub fn display(
+    files: &[MyFile],
+    output: &mut Option<File>,
+    colors: &Colors,
+) -> Option<Vec<String>> {
     if let Some(outfile) = output {
-        for s in res {
-            writeln!(outfile, "{}", s).expect("Unable to write to file");
+        for f in files {
+            writeln!(outfile, "{}", f.path).expect("Unable to write to file");
         }
         None
     } else {
-        Some(res)
+        Some(files.iter().map(|f| colors.file(f, &f.path)).collect())
     }
 }
 
@@ -380,38 +1607,102 @@ pub struct TrConfig<'a> {
     pub delete: Option<&'a str>,
     pub replace: Vec<&'a str>,
     pub simulate: bool,
+    pub backup: Option<&'a str>,
+    pub preserve_mtime: bool,
+    pub encoding: Option<&'a str>,
+    pub binary_safe: bool,
+    pub lines: Option<&'a str>,
+    pub after: Option<&'a str>,
+    pub between: Vec<&'a str>,
+    pub journal: bool,
+    pub log: Logger,
 }
 
 pub fn run_tr(config: &TrConfig) -> Result<(), &'static str> {
-    let mut content: Option<String> = config.parse_file_path();
+    let raw: Option<Vec<u8>> = config.parse_file_path();
     let delete: Option<&str> = config.delete;
     let _file: Option<File> = config.parse_file();
     let path: Option<PathBuf> = config.parse_path();
     let v_replace: Option<Vec<&str>> = config.parse_replace();
+    let encoding: Option<TextEncoding> = config.parse_encoding()?;
 
-    // call aux functions for implementing delete & replace
-    if delete.is_some() {
-        content = delete_words(&mut content, delete);
-    }
+    let raw = match raw {
+        Some(r) => r,
+        None => return Ok(()),
+    };
 
-    if let Some(r) = v_replace {
-        content = replace_words(&mut content, r);
-    }
+    let output: Vec<u8> = if config.binary_safe {
+        // operate on raw bytes, nothing is decoded or normalised
+        let mut bytes = raw;
+        if let Some(d) = delete {
+            bytes = replace_bytes(&bytes, d.as_bytes(), b"");
+        }
+        if let Some(r) = v_replace {
+            bytes = replace_bytes(&bytes, r[0].as_bytes(), r[1].as_bytes());
+        }
+        if config.simulate {
+            io::stdout()
+                .write_all(&bytes)
+                .map_err(|_| "Unable to write to stdout")?;
+            return Ok(());
+        }
+        bytes
+    } else {
+        let text = TextFile::decode(&raw, encoding)?;
+        let selection: LineSelection = config.parse_selection()?;
+        let edit = |s: &str| -> String {
+            let mut content: Option<String> = Some(s.to_string());
 
-    if let Some(c) = content {
+            // call aux functions for implementing delete & replace
+            if delete.is_some() {
+                content = delete_words(&mut content, delete);
+            }
+
+            if let Some(r) = &v_replace {
+                content = replace_words(&mut content, r.clone());
+            }
+
+            content.unwrap_or_default()
+        };
+
+        let c = if selection.is_whole_file() {
+            edit(&text.content)
+        } else {
+            edit_selected_lines(&text.content, &selection, edit)
+        };
         if config.simulate {
             println!("{}", c);
-        } else if let Some(p) = path {
-            // writeln!(file.unwrap(), "{}", c).expect("Unable to write to file");
-            if let Some(f) = config.file {
-                let file_path = p.join(f);
-                match fs::write(file_path, c) {
-                    Ok(_) => {
-                        println!("Your operation is successful this time!")
-                    }
-                    Err(err) => {
-                        eprintln!("Failed to write to file, {}", err);
+            return Ok(());
+        }
+        text.encode(&c)?
+    };
+
+    if let Some(p) = path {
+        // writeln!(file.unwrap(), "{}", c).expect("Unable to write to file");
+        if let Some(f) = config.file {
+            let file_path = p.join(f);
+            let opts = config.parse_write_options();
+            let mut record: Option<JournalRecord> = begin_journal(config.journal, "tr")?;
+            if let Some(r) = &mut record {
+                r.save_contents(&file_path)
+                    .map_err(|_| "Could not save the original file to the undo journal")?;
+            }
+            match atomic_write(&file_path, &output, &opts, &config.log) {
+                Ok(_) => {
+                    commit_journal(record, &config.log);
+                    info!(config.log, "Wrote file"; "path" => %file_path.display());
+                    println!("Your operation is successful this time!")
+                }
+                Err(err) => {
+                    if let Some(r) = record {
+                        r.discard();
                     }
+                    error!(
+                        config.log,
+                        "Failed to write to file";
+                        "path" => %file_path.display(),
+                        "error" => %err
+                    );
                 }
             }
         }
@@ -421,11 +1712,22 @@ pub fn run_tr(config: &TrConfig) -> Result<(), &'static str> {
 }
 
 impl<'a> TrConfig<'a> {
-    pub fn from_args(args: &'a ArgMatches) -> Self {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
         let path: Option<&'a str> = args.value_of("path");
         let file: Option<&'a str> = args.value_of("file");
         let delete: Option<&'a str> = args.value_of("delete");
         let simulate: bool = args.is_present("simulate");
+        let backup: Option<&'a str> = args.value_of("backup");
+        let preserve_mtime: bool = args.is_present("preserve-mtime");
+        let encoding: Option<&'a str> = args.value_of("encoding");
+        let binary_safe: bool = args.is_present("binary-safe");
+        let lines: Option<&'a str> = args.value_of("lines");
+        let after: Option<&'a str> = args.value_of("after");
+        let journal: bool = !args.is_present("no-journal");
+        let mut between: Vec<&'a str> = Vec::new();
+        if let Some(val) = args.values_of("between") {
+            between = val.collect();
+        }
         let mut replace: Vec<&'a str> = Vec::new();
         if let Some(val) = args.values_of("replace") {
             replace = val.collect();
@@ -437,6 +1739,63 @@ impl<'a> TrConfig<'a> {
             delete,
             replace,
             simulate,
+            backup,
+            preserve_mtime,
+            encoding,
+            binary_safe,
+            lines,
+            after,
+            between,
+            journal,
+            log: log.new(o!("command" => "tr")),
+        }
+    }
+
+    pub fn parse_selection(&self) -> Result<LineSelection, &'static str> {
+        let lines = match self.lines {
+            Some(l) => Some(
+                parse_line_range(l)
+                    .ok_or("Invalid line range, expected N, START:END, START: or :END")?,
+            ),
+            None => None,
+        };
+        let after = match self.after {
+            Some(a) => {
+                Some(Regex::new(a).map_err(|_| "--after is not a valid regular expression")?)
+            }
+            None => None,
+        };
+        let between = match self.between[..] {
+            [] => None,
+            [start, end] => Some((
+                Regex::new(start)
+                    .map_err(|_| "--between start is not a valid regular expression")?,
+                Regex::new(end).map_err(|_| "--between end is not a valid regular expression")?,
+            )),
+            _ => return Err("--between takes a start and an end regular expression"),
+        };
+
+        Ok(LineSelection {
+            lines,
+            after,
+            between,
+        })
+    }
+
+    /// `None` means the encoding should be detected from the file contents.
+    pub fn parse_encoding(&self) -> Result<Option<TextEncoding>, &'static str> {
+        match self.encoding {
+            None | Some("auto") => Ok(None),
+            Some(name) => TextEncoding::from_name(name)
+                .map(Some)
+                .ok_or("Unsupported encoding, expected one of utf-8, utf-8-bom, utf-16le, utf-16be, latin1"),
+        }
+    }
+
+    pub fn parse_write_options(&self) -> WriteOptions<'_> {
+        WriteOptions {
+            backup: self.backup.filter(|s| !s.is_empty()),
+            preserve_mtime: self.preserve_mtime,
         }
     }
 
@@ -451,7 +1810,7 @@ impl<'a> TrConfig<'a> {
                         res = Some(r);
                     }
                     Err(err) => {
-                        eprintln!("Failed to open file {}: {}", f, err);
+                        error!(self.log, "Failed to open file"; "file" => f, "error" => %err);
                     }
                 }
             }
@@ -480,25 +1839,25 @@ impl<'a> TrConfig<'a> {
             if path.is_dir() {
                 res = Some(path);
             } else {
-                eprintln!("{} is an invalid directory or is inaccessible", p);
+                warn!(self.log, "Invalid or inaccessible directory, ignoring"; "dir" => p);
             }
         }
         res
     }
 
-    // need to address borrowing issues in this function
-    pub fn parse_file_path(&self) -> Option<String> {
-        let mut res: Option<String> = None;
+    // raw bytes, decoding is left to `TextFile::decode` so non UTF-8 files can be read
+    pub fn parse_file_path(&self) -> Option<Vec<u8>> {
+        let mut res: Option<Vec<u8>> = None;
 
         if let Some(p) = self.parse_path() {
             if let Some(f) = self.file {
                 let file_path = p.join(f);
-                match fs::read_to_string(file_path) {
+                match fs::read(file_path) {
                     Ok(r) => {
                         res = Some(r);
                     }
                     Err(err) => {
-                        eprintln!("Failed to read file {}: {}", f, err);
+                        error!(self.log, "Failed to read file"; "file" => f, "error" => %err);
                     }
                 }
             }
@@ -508,6 +1867,386 @@ impl<'a> TrConfig<'a> {
     }
 }
 
+/// Parse a 1-based, inclusive `--lines` range: `N`, `START:END`, `START:` or `:END`.
+pub fn parse_line_range(range: &str) -> Option<(usize, usize)> {
+    let (start, end) = match range.split_once(':') {
+        Some((s, e)) => (s.trim(), e.trim()),
+        None => (range.trim(), range.trim()),
+    };
+    let start = if start.is_empty() {
+        1
+    } else {
+        start.parse().ok()?
+    };
+    let end = if end.is_empty() {
+        usize::MAX
+    } else {
+        end.parse().ok()?
+    };
+    if start == 0 || end < start {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// Which lines of a file `tr` is allowed to edit. Every given address has to
+/// select a line for it to be edited.
+pub struct LineSelection {
+    /// 1-based inclusive line range.
+    pub lines: Option<(usize, usize)>,
+    /// Lines following the first line that matches.
+    pub after: Option<Regex>,
+    /// Blocks from a line matching the start regex up to and including the next
+    /// line matching the end regex, like sed's `/start/,/end/`.
+    pub between: Option<(Regex, Regex)>,
+}
+
+impl LineSelection {
+    pub fn is_whole_file(&self) -> bool {
+        self.lines.is_none() && self.after.is_none() && self.between.is_none()
+    }
+
+    pub fn select(&self, lines: &[&str]) -> Vec<bool> {
+        let mut res = vec![true; lines.len()];
+
+        if let Some((start, end)) = self.lines {
+            for (i, sel) in res.iter_mut().enumerate() {
+                *sel &= i + 1 >= start && i < end;
+            }
+        }
+
+        if let Some(rgx) = &self.after {
+            let first = lines.iter().position(|l| rgx.is_match(l));
+            for (i, sel) in res.iter_mut().enumerate() {
+                *sel &= matches!(first, Some(f) if i > f);
+            }
+        }
+
+        if let Some((start, end)) = &self.between {
+            let mut inside = false;
+            for (line, sel) in lines.iter().zip(res.iter_mut()) {
+                let in_block = if inside {
+                    inside = !end.is_match(line);
+                    true
+                } else {
+                    inside = start.is_match(line);
+                    inside
+                };
+                *sel &= in_block;
+            }
+        }
+
+        res
+    }
+}
+
+/// Apply `edit` to the lines picked by `selection`, leaving the others untouched.
+pub fn edit_selected_lines<F: Fn(&str) -> String>(
+    content: &str,
+    selection: &LineSelection,
+    edit: F,
+) -> String {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let selected = selection.select(&lines);
+    let mut res = String::with_capacity(content.len());
+    for (line, sel) in lines.iter().zip(selected) {
+        if sel {
+            // keep the line break out of reach of the edit
+            let (body, newline) = match line.strip_suffix('\n') {
+                Some(b) => (b, "\n"),
+                None => (*line, ""),
+            };
+            res.push_str(&edit(body));
+            res.push_str(newline);
+        } else {
+            res.push_str(line);
+        }
+    }
+    res
+}
+
+/// Text encodings `tr` can read and write back unchanged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl TextEncoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(TextEncoding::Utf8),
+            "utf-8-bom" | "utf8-bom" => Some(TextEncoding::Utf8Bom),
+            "utf-16le" | "utf16le" => Some(TextEncoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(TextEncoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(TextEncoding::Latin1),
+            _ => None,
+        }
+    }
+
+    /// Guess the encoding from a byte order mark, falling back to Latin-1
+    /// (which accepts any byte sequence) when the bytes aren't valid UTF-8.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            TextEncoding::Utf8Bom
+        } else if bytes.starts_with(&[0xFF, 0xFE]) {
+            TextEncoding::Utf16Le
+        } else if bytes.starts_with(&[0xFE, 0xFF]) {
+            TextEncoding::Utf16Be
+        } else if std::str::from_utf8(bytes).is_ok() {
+            TextEncoding::Utf8
+        } else {
+            TextEncoding::Latin1
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<String, &'static str> {
+        match self {
+            TextEncoding::Utf8 | TextEncoding::Utf8Bom => {
+                let body = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(bytes);
+                String::from_utf8(body.to_vec()).map_err(|_| "File is not valid UTF-8")
+            }
+            TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+                if !bytes.len().is_multiple_of(2) {
+                    return Err("File is not valid UTF-16");
+                }
+                let units = bytes.chunks(2).map(|c| {
+                    if *self == TextEncoding::Utf16Le {
+                        u16::from_le_bytes([c[0], c[1]])
+                    } else {
+                        u16::from_be_bytes([c[0], c[1]])
+                    }
+                });
+                let mut res: String = char::decode_utf16(units)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| "File is not valid UTF-16")?;
+                if res.starts_with('\u{FEFF}') {
+                    res.remove(0);
+                }
+                Ok(res)
+            }
+            TextEncoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+        }
+    }
+
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, &'static str> {
+        match self {
+            TextEncoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            TextEncoding::Utf8Bom => Ok([&[0xEF, 0xBB, 0xBF], text.as_bytes()].concat()),
+            TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+                let mut res = Vec::with_capacity(text.len() * 2);
+                for unit in text.encode_utf16() {
+                    if *self == TextEncoding::Utf16Le {
+                        res.extend_from_slice(&unit.to_le_bytes());
+                    } else {
+                        res.extend_from_slice(&unit.to_be_bytes());
+                    }
+                }
+                Ok(res)
+            }
+            TextEncoding::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(c as u32).map_err(|_| "Text cannot be encoded as Latin-1"))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// CRLF only if every line break is one, so files with mixed endings are left alone.
+    pub fn detect(text: &str) -> Self {
+        let lf = text.matches('\n').count();
+        if lf > 0 && text.matches("\r\n").count() == lf {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        }
+    }
+}
+
+/// Decoded file contents plus what's needed to write them back the same way.
+pub struct TextFile {
+    /// Contents with CRLF line breaks normalised to `\n`.
+    pub content: String,
+    pub encoding: TextEncoding,
+    /// Whether a UTF-8 or UTF-16 file started with a byte order mark.
+    pub bom: bool,
+    pub line_ending: LineEnding,
+}
+
+impl TextFile {
+    /// Decode `bytes` with `encoding`, or a detected encoding if it is `None`.
+    /// UTF-16 files without a byte order mark must be given an explicit encoding.
+    pub fn decode(bytes: &[u8], encoding: Option<TextEncoding>) -> Result<Self, &'static str> {
+        let encoding = encoding.unwrap_or_else(|| TextEncoding::detect(bytes));
+        let decoded = encoding.decode(bytes)?;
+        let bom = match encoding {
+            // an explicit utf-8 keeps the mark that decoding stripped
+            TextEncoding::Utf8 => bytes.starts_with(&[0xEF, 0xBB, 0xBF]),
+            TextEncoding::Utf16Le => bytes.starts_with(&[0xFF, 0xFE]),
+            TextEncoding::Utf16Be => bytes.starts_with(&[0xFE, 0xFF]),
+            _ => false,
+        };
+        let line_ending = LineEnding::detect(&decoded);
+        let content = match line_ending {
+            LineEnding::CrLf => decoded.replace("\r\n", "\n"),
+            LineEnding::Lf => decoded,
+        };
+
+        Ok(TextFile {
+            content,
+            encoding,
+            bom,
+            line_ending,
+        })
+    }
+
+    /// Encode `content` using the original encoding, BOM and line endings.
+    pub fn encode(&self, content: &str) -> Result<Vec<u8>, &'static str> {
+        let mut text = match self.line_ending {
+            LineEnding::CrLf => content.replace('\n', "\r\n"),
+            LineEnding::Lf => content.to_string(),
+        };
+        if self.bom {
+            text.insert(0, '\u{FEFF}');
+        }
+        self.encoding.encode(&text)
+    }
+}
+
+/// Byte-level counterpart of `str::replace`, used by `tr --binary-safe`.
+pub fn replace_bytes(haystack: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    if from.is_empty() {
+        return haystack.to_vec();
+    }
+    let mut res = Vec::with_capacity(haystack.len());
+    let mut i = 0;
+    while i < haystack.len() {
+        if haystack[i..].starts_with(from) {
+            res.extend_from_slice(to);
+            i += from.len();
+        } else {
+            res.push(haystack[i]);
+            i += 1;
+        }
+    }
+    res
+}
+
+/// Options for `atomic_write`.
+pub struct WriteOptions<'a> {
+    /// Keep the original file next to the target with this suffix appended (e.g. `~`).
+    pub backup: Option<&'a str>,
+    /// Carry the original access/modification times over to the new contents.
+    pub preserve_mtime: bool,
+}
+
+/// Replace the contents of `target` without ever leaving it half-written.
+///
+/// The new contents go to a temporary file in the same directory, which is
+/// fsynced, given the mode and ownership of the original and then renamed over
+/// it. Symlinks are followed so the link itself is left in place.
+pub fn atomic_write(
+    target: &Path,
+    contents: &[u8],
+    opts: &WriteOptions,
+    log: &Logger,
+) -> io::Result<()> {
+    let target = fs::canonicalize(target).unwrap_or_else(|_| target.to_path_buf());
+    let dir = match target.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = target
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "target has no file name"))?
+        .to_string_lossy()
+        .to_string();
+    let meta = fs::metadata(&target).ok();
+
+    let (tmp_path, mut tmp) = create_temp_file(&dir, &name)?;
+    let res = (|| {
+        tmp.write_all(contents)?;
+        if let Some(m) = &meta {
+            tmp.set_permissions(m.permissions())?;
+            if m.uid() != tmp.metadata()?.uid() || m.gid() != tmp.metadata()?.gid() {
+                if let Err(err) = fchown(&tmp, Some(m.uid()), Some(m.gid())) {
+                    warn!(
+                        log,
+                        "Could not preserve ownership";
+                        "path" => %target.display(),
+                        "error" => %err
+                    );
+                }
+            }
+            if opts.preserve_mtime {
+                let times = FileTimes::new()
+                    .set_accessed(m.accessed()?)
+                    .set_modified(m.modified()?);
+                tmp.set_times(times)?;
+            }
+        }
+        tmp.sync_all()?;
+
+        if let (Some(suffix), Some(_)) = (opts.backup, &meta) {
+            let backup = dir.join(format!("{}{}", name, suffix));
+            // a hard link keeps the original reachable under both names until the rename
+            let _ = fs::remove_file(&backup);
+            if fs::hard_link(&target, &backup).is_err() {
+                fs::copy(&target, &backup)?;
+            }
+        }
+        fs::rename(&tmp_path, &target)
+    })();
+
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return res;
+    }
+    // make the rename itself durable
+    if let Ok(d) = File::open(&dir) {
+        let _ = d.sync_all();
+    }
+    Ok(())
+}
+
+/// Create a new, uniquely named hidden file in `dir` for staging writes to `name`.
+fn create_temp_file(dir: &Path, name: &str) -> io::Result<(PathBuf, File)> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let mut attempt = 0;
+    loop {
+        let tmp_path = dir.join(format!(
+            ".{}.{}.{}.tmp",
+            name,
+            std::process::id(),
+            nanos.wrapping_add(attempt)
+        ));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)
+        {
+            Ok(f) => return Ok((tmp_path, f)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
 pub fn delete_words(content: &mut Option<String>, delete: Option<&str>) -> Option<String> {
     let mut res = String::from("");
     if let Some(c) = content {
@@ -534,33 +2273,31 @@ pub fn replace_words(content: &mut Option<String>, replace: Vec<&str>) -> Option
 pub struct GrepConfig<'a> {
     pub patterns: Vec<&'a str>,
     pub filenames: Vec<&'a str>,
+    pub search_zip: bool,
+    pub archives: bool,
+    pub color: Option<&'a str>,
+    pub log: Logger,
 }
 
 pub fn run_grep(config: &GrepConfig) -> Result<(), &'static str> {
     let v_patterns: Vec<Regex> = config.parse_patterns()?;
     let v_files: Vec<&str> = config.parse_files()?;
+    let colors: Colors = parse_color(config.color)?;
 
     for pattern in v_patterns {
         println!("Searching for lines matching {}", pattern);
         for filename in &v_files {
-            let mut f = File::open(filename).unwrap();
-            let mut contents = String::new();
-
-            // https://stackoverflow.com/questions/53368303/why-am-i-getting-unused-result-which-must-be-used-result-may-be-an-err-vari/53368681#53368681
-            if let Err(e) = f.read_to_string(&mut contents) {
-                eprintln!("{:?}", e)
+            if config.archives && grep_archive(config, filename, &pattern, &colors) {
+                continue;
             }
-
-            let mut line_number = 1;
-            println!("Looking inside {}", filename);
-            for line in contents.lines() {
-                // if line.contains(pattern) {
-                if pattern.is_match(line) {
-                    println!("{}: {}", line_number, line);
+            let reader = match open_search_reader(Path::new(filename), config.search_zip) {
+                Ok(r) => r,
+                Err(e) => {
+                    error!(config.log, "Could not open file"; "file" => filename, "error" => %e);
+                    continue;
                 }
-                line_number += 1;
-            }
-            println!("Done looking inside {}", filename);
+            };
+            grep_one(filename, reader, &pattern, &colors, &config.log);
         }
         println!("Done searching for lines matching {}", pattern);
     }
@@ -568,32 +2305,198 @@ pub fn run_grep(config: &GrepConfig) -> Result<(), &'static str> {
     Ok(())
 }
 
-impl<'a> GrepConfig<'a> {
-    pub fn from_args(args: &'a ArgMatches) -> Self {
-        let patterns: Vec<&'a str> = args.values_of("patterns").unwrap().collect();
-        let filenames: Vec<&'a str> = args.values_of("filenames").unwrap().collect();
-
-        GrepConfig {
-            patterns,
-            filenames,
+/// Search the files inside an archive, or a single `archive!/member`, without
+/// extracting them. Returns false if `filename` is neither.
+fn grep_archive(config: &GrepConfig, filename: &str, pattern: &Regex, colors: &Colors) -> bool {
+    let (archive, member) = match ArchiveFormat::from_path(Path::new(filename)) {
+        Some(_) => (Path::new(filename), None),
+        None => match split_archive_path(filename) {
+            Some((a, m)) => (a, Some(m)),
+            None => return false,
+        },
+    };
+    let format = match ArchiveFormat::from_path(archive) {
+        Some(f) if archive.is_file() => f,
+        _ => return false,
+    };
+    let res = for_each_archive_entry(archive, format, |name, data| {
+        if member.is_some_and(|m| m != name) {
+            return Ok(());
         }
+        let reader: Box<dyn BufRead + '_> = Box::new(io::BufReader::new(data));
+        let reader = if config.search_zip {
+            decompressing_reader(reader, Path::new(name))?
+        } else {
+            reader
+        };
+        grep_one(
+            &archive_member_path(archive, name),
+            reader,
+            pattern,
+            colors,
+            &config.log,
+        );
+        Ok(())
+    });
+    if let Err(e) = res {
+        error!(
+            config.log,
+            "Could not read the archive";
+            "archive" => %archive.display(),
+            "error" => %e
+        );
+    }
+    true
+}
+
+fn grep_one(filename: &str, reader: impl BufRead, pattern: &Regex, colors: &Colors, log: &Logger) {
+    println!("Looking inside {}", colors.file_name(filename));
+    let res = grep_reader(reader, pattern, |line_number, line| {
+        println!("{}", colors.grep_line(line_number, line, pattern));
+    });
+    if let Err(e) = res {
+        error!(log, "Could not read file"; "file" => filename, "error" => %e);
     }
+    println!("Done looking inside {}", filename);
+}
 
-    pub fn parse_patterns(&self) -> Result<Vec<Regex>, &'static str> {
-        let mut res: Vec<Regex> = Vec::new();
-        let mut parsed = false;
-        for p in &self.patterns {
-            if let Ok(rgx) = Regex::new(p) {
-                res.push(rgx);
-                parsed = true;
-            } else {
-                eprintln!("{} is not a valid regular expression, ignoring", p);
-            }
+/// Call `on_match` with the number and text of every line of `reader` that
+/// matches `pattern`, one line at a time. Invalid UTF-8 is replaced rather
+/// than ending the search.
+pub fn grep_reader<R: BufRead>(
+    mut reader: R,
+    pattern: &Regex,
+    mut on_match: impl FnMut(usize, &str),
+) -> io::Result<()> {
+    let mut buf: Vec<u8> = Vec::new();
+    let mut line_number = 0;
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            return Ok(());
         }
-        if parsed {
-            Ok(res)
-        } else {
-            Err("No valid regex given")
+        line_number += 1;
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if pattern.is_match(line) {
+            on_match(line_number, line);
+        }
+    }
+}
+
+/// Compressed formats `grep -z` can look into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionFormat {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl CompressionFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(CompressionFormat::Gzip),
+            "bz2" => Some(CompressionFormat::Bzip2),
+            "xz" => Some(CompressionFormat::Xz),
+            "zst" => Some(CompressionFormat::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Recognize the format from the first bytes of the data.
+    pub fn from_magic(head: &[u8]) -> Option<Self> {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Some(CompressionFormat::Gzip)
+        } else if head.starts_with(b"BZh") {
+            Some(CompressionFormat::Bzip2)
+        } else if head.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(CompressionFormat::Xz)
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(CompressionFormat::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Open `path` for reading line by line. With `decompress`, compressed data
+/// is decompressed on the fly, see `decompressing_reader`.
+pub fn open_search_reader(path: &Path, decompress: bool) -> io::Result<Box<dyn BufRead>> {
+    let reader: Box<dyn BufRead> = Box::new(io::BufReader::new(File::open(path)?));
+    if decompress {
+        decompressing_reader(reader, path)
+    } else {
+        Ok(reader)
+    }
+}
+
+/// Wrap `reader` so gzip, bzip2, xz and zstd data comes out decompressed,
+/// recognized by its magic bytes or else by the extension of `name`. Other
+/// data is passed through. Concatenated streams are read to the end.
+pub fn decompressing_reader<'a>(
+    mut reader: Box<dyn BufRead + 'a>,
+    name: &Path,
+) -> io::Result<Box<dyn BufRead + 'a>> {
+    let format = CompressionFormat::from_magic(reader.fill_buf()?)
+        .or_else(|| CompressionFormat::from_path(name));
+    Ok(match format {
+        Some(CompressionFormat::Gzip) => Box::new(io::BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(reader),
+        )),
+        Some(CompressionFormat::Bzip2) => Box::new(io::BufReader::new(
+            bzip2::bufread::MultiBzDecoder::new(reader),
+        )),
+        Some(CompressionFormat::Xz) => Box::new(io::BufReader::new(
+            xz2::bufread::XzDecoder::new_multi_decoder(reader),
+        )),
+        Some(CompressionFormat::Zstd) => Box::new(io::BufReader::new(
+            zstd::stream::read::Decoder::with_buffer(reader)?,
+        )),
+        None => reader,
+    })
+}
+
+impl<'a> GrepConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
+        let patterns: Vec<&'a str> = args.values_of("patterns").unwrap().collect();
+        let filenames: Vec<&'a str> = args.values_of("filenames").unwrap().collect();
+        let search_zip: bool = args.is_present("search-zip");
+        let archives: bool = args.is_present("archives");
+        let color: Option<&'a str> = args.value_of("color");
+
+        GrepConfig {
+            patterns,
+            filenames,
+            search_zip,
+            archives,
+            color,
+            log: log.new(o!("command" => "grep")),
+        }
+    }
+
+    pub fn parse_patterns(&self) -> Result<Vec<Regex>, &'static str> {
+        let mut res: Vec<Regex> = Vec::new();
+        let mut parsed = false;
+        for p in &self.patterns {
+            match Regex::new(p) {
+                Ok(rgx) => {
+                    res.push(rgx);
+                    parsed = true;
+                }
+                Err(err) => warn!(
+                    self.log,
+                    "Invalid regular expression, ignoring";
+                    "pattern" => p,
+                    "error" => regex_error_line(&err)
+                ),
+            }
+        }
+        if parsed {
+            Ok(res)
+        } else {
+            Err("No valid regex given")
         }
     }
 
@@ -608,3 +2511,4835 @@ impl<'a> GrepConfig<'a> {
     }
 }
 /**************************** rust_grep ends *****************************/
+
+/**************************** rust_undo starts *****************************/
+
+pub struct UndoConfig<'a> {
+    pub id: Option<&'a str>,
+    pub list: bool,
+    pub log: Logger,
+}
+
+pub fn run_undo(config: &UndoConfig) -> Result<(), &'static str> {
+    let journal = Journal::open().map_err(|_| "Could not open the undo journal")?;
+
+    if config.list {
+        let entries = journal.entries();
+        if entries.is_empty() {
+            println!("Nothing to undo");
+        }
+        for e in entries {
+            let time = Local
+                .timestamp_millis_opt(e.time as i64)
+                .single()
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default();
+            println!(
+                "{}  {}  {:<6}  {} change(s)",
+                e.id,
+                time,
+                e.command,
+                e.actions.len()
+            );
+        }
+        return Ok(());
+    }
+
+    let entry = journal.undo(config.id, &config.log)?;
+    info!(config.log, "Undid operation"; "id" => &entry.id, "operation" => &entry.command);
+    println!("Undid {} operation {}", entry.command, entry.id);
+    Ok(())
+}
+
+impl<'a> UndoConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
+        let id: Option<&'a str> = args.value_of("id");
+        let list: bool = args.is_present("list");
+
+        UndoConfig {
+            id,
+            list,
+            log: log.new(o!("command" => "undo")),
+        }
+    }
+}
+
+/// One reversible change made by a mutating command.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum JournalAction {
+    /// `path` was overwritten, its previous contents are stored in `saved`.
+    Modified { path: PathBuf, saved: PathBuf },
+    /// `path` was removed after being moved to `saved`.
+    Removed { path: PathBuf, saved: PathBuf },
+    /// `path` did not exist before the operation and was created with `size` bytes.
+    Created {
+        path: PathBuf,
+        #[serde(default)]
+        size: u64,
+    },
+    /// The timestamps of `path` were changed from these.
+    Touched {
+        path: PathBuf,
+        accessed: SystemTime,
+        modified: SystemTime,
+    },
+    /// `path` was moved to the trash as `trashed`, described by the `info` file.
+    Trashed {
+        path: PathBuf,
+        trashed: PathBuf,
+        info: PathBuf,
+    },
+    /// `path` was moved to `to`.
+    Moved { path: PathBuf, to: PathBuf },
+}
+
+/// Everything one invocation of a command that changes files did, so it can be undone.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JournalEntry {
+    pub id: String,
+    pub command: String,
+    /// Milliseconds since the unix epoch.
+    pub time: u64,
+    pub actions: Vec<JournalAction>,
+}
+
+/// Operation journal kept under `$XDG_STATE_HOME/rust_file_manager/journal`,
+/// with one directory per operation holding `entry.json` and any saved files.
+pub struct Journal {
+    pub dir: PathBuf,
+}
+
+impl Journal {
+    pub fn open() -> io::Result<Self> {
+        let state = match std::env::var_os("XDG_STATE_HOME") {
+            Some(d) if !d.is_empty() => PathBuf::from(d),
+            _ => home_dir()?.join(".local/state"),
+        };
+        Ok(Journal::at(&state.join("rust_file_manager/journal")))
+    }
+
+    pub fn at(dir: &Path) -> Self {
+        Journal {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Start recording a new operation.
+    pub fn begin(&self, command: &str) -> io::Result<JournalRecord> {
+        fs::create_dir_all(&self.dir)?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        // ids sort in creation order, bump the timestamp if two operations collide
+        let mut n = time;
+        loop {
+            let id = n.to_string();
+            let dir = self.dir.join(&id);
+            match fs::create_dir(&dir) {
+                Ok(_) => {
+                    return Ok(JournalRecord {
+                        dir,
+                        entry: JournalEntry {
+                            id,
+                            command: command.to_string(),
+                            time,
+                            actions: Vec::new(),
+                        },
+                    })
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => n += 1,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// All recorded operations, oldest first.
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        let mut res: Vec<JournalEntry> = Vec::new();
+        if let Ok(readdir) = fs::read_dir(&self.dir) {
+            for entry in readdir.flatten() {
+                if let Ok(json) = fs::read_to_string(entry.path().join("entry.json")) {
+                    if let Ok(e) = serde_json::from_str(&json) {
+                        res.push(e);
+                    }
+                }
+            }
+        }
+        res.sort_by_key(|e| e.id.parse::<u64>().unwrap_or(0));
+        res
+    }
+
+    /// Drop the oldest operations until at most `max_entries` are left and they
+    /// take up no more than `max_bytes` on disk. Returns the ids dropped.
+    pub fn prune(&self, max_entries: usize, max_bytes: u64, log: &Logger) -> Vec<String> {
+        let entries = self.entries();
+        let sizes: Vec<u64> = entries
+            .iter()
+            .map(|e| disk_usage(&self.dir.join(&e.id), false, log).allocated)
+            .collect();
+        let mut count = entries.len();
+        let mut total: u64 = sizes.iter().sum();
+        let mut dropped: Vec<String> = Vec::new();
+        for (e, size) in entries.into_iter().zip(sizes) {
+            if count <= max_entries && total <= max_bytes {
+                break;
+            }
+            match fs::remove_dir_all(self.dir.join(&e.id)) {
+                Ok(_) => dropped.push(e.id),
+                Err(err) => {
+                    warn!(log, "Could not prune the undo journal"; "id" => &e.id, "error" => %err)
+                }
+            }
+            count -= 1;
+            total -= size;
+        }
+        dropped
+    }
+
+    /// Revert the operation `id`, or the most recent one, and drop it from the journal.
+    pub fn undo(&self, id: Option<&str>, log: &Logger) -> Result<JournalEntry, &'static str> {
+        let mut entry = match id {
+            Some(id) => self.entries().into_iter().find(|e| e.id == id),
+            None => self.entries().pop(),
+        }
+        .ok_or("No such operation in the undo journal")?;
+
+        let mut failed: Vec<JournalAction> = Vec::new();
+        for action in std::mem::take(&mut entry.actions).into_iter().rev() {
+            let res = match &action {
+                JournalAction::Modified { path, saved } => move_path(saved, path, log),
+                JournalAction::Removed { path, saved } => {
+                    if path.exists() {
+                        Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            "a file with that name exists again",
+                        ))
+                    } else {
+                        move_path(saved, path, log)
+                    }
+                }
+                JournalAction::Created { path, size } => match fs::symlink_metadata(path) {
+                    // directories are only removed if they are empty again
+                    Ok(m) if m.is_dir() => fs::remove_dir(path),
+                    // don't throw away anything written since it was created
+                    Ok(m) if m.len() != *size => {
+                        Err(io::Error::other("it was modified after being created"))
+                    }
+                    Ok(_) => fs::remove_file(path),
+                    Err(_) => Ok(()),
+                },
+                JournalAction::Touched {
+                    path,
+                    accessed,
+                    modified,
+                } => set_path_times(
+                    path,
+                    &TouchTimes {
+                        accessed: Some(*accessed),
+                        modified: Some(*modified),
+                    },
+                ),
+                JournalAction::Trashed {
+                    path,
+                    trashed,
+                    info,
+                } => {
+                    if path.exists() {
+                        Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            "a file with that name exists again",
+                        ))
+                    } else {
+                        move_path(trashed, path, log).and_then(|_| fs::remove_file(info))
+                    }
+                }
+                JournalAction::Moved { path, to } => move_path_no_clobber(to, path, log),
+            };
+            if let Err(err) = res {
+                error!(
+                    log,
+                    "Could not undo change";
+                    "path" => %action_path(&action).display(),
+                    "error" => %err
+                );
+                failed.push(action);
+            }
+        }
+
+        let dir = self.dir.join(&entry.id);
+        if failed.is_empty() {
+            let _ = fs::remove_dir_all(dir);
+            Ok(entry)
+        } else {
+            // keep only what is left to undo so the next attempt can retry it
+            failed.reverse();
+            entry.actions = failed;
+            let record = JournalRecord { dir, entry };
+            let _ = record.commit();
+            Err("Some changes could not be undone, they were kept in the journal")
+        }
+    }
+}
+
+fn action_path(action: &JournalAction) -> &Path {
+    match action {
+        JournalAction::Modified { path, .. }
+        | JournalAction::Removed { path, .. }
+        | JournalAction::Created { path, .. }
+        | JournalAction::Touched { path, .. }
+        | JournalAction::Trashed { path, .. }
+        | JournalAction::Moved { path, .. } => path,
+    }
+}
+
+/// An operation that is being recorded, written to disk by `commit`.
+pub struct JournalRecord {
+    pub dir: PathBuf,
+    pub entry: JournalEntry,
+}
+
+impl JournalRecord {
+    fn next_slot(&self) -> PathBuf {
+        self.dir.join(self.entry.actions.len().to_string())
+    }
+
+    /// Keep a copy of `path` so it can be restored after being overwritten.
+    pub fn save_contents(&mut self, path: &Path) -> io::Result<()> {
+        let saved = self.next_slot();
+        fs::copy(path, &saved)?;
+        self.entry.actions.push(JournalAction::Modified {
+            path: std::path::absolute(path)?,
+            saved,
+        });
+        Ok(())
+    }
+
+    /// Whether the file `meta` describes can be moved into the journal by a
+    /// rename. From another filesystem it has to be copied.
+    pub fn same_filesystem(&self, meta: &fs::Metadata) -> bool {
+        fs::metadata(&self.dir).is_ok_and(|d| d.dev() == meta.dev())
+    }
+
+    /// Remove `path` by moving it into the journal.
+    pub fn move_aside(&mut self, path: &Path, log: &Logger) -> io::Result<()> {
+        let saved = self.next_slot();
+        let abs = std::path::absolute(path)?;
+        move_path(path, &saved, log)?;
+        self.entry
+            .actions
+            .push(JournalAction::Removed { path: abs, saved });
+        Ok(())
+    }
+
+    pub fn trashed(&mut self, item: &TrashItem) {
+        self.entry.actions.push(JournalAction::Trashed {
+            path: item.path.clone(),
+            trashed: item.file.clone(),
+            info: item.info.clone(),
+        });
+    }
+
+    pub fn moved(&mut self, path: &Path, to: &Path) -> io::Result<()> {
+        self.entry.actions.push(JournalAction::Moved {
+            path: std::path::absolute(path)?,
+            to: std::path::absolute(to)?,
+        });
+        Ok(())
+    }
+
+    pub fn touched(&mut self, path: &Path, meta: &fs::Metadata) -> io::Result<()> {
+        self.entry.actions.push(JournalAction::Touched {
+            path: std::path::absolute(path)?,
+            accessed: meta.accessed()?,
+            modified: meta.modified()?,
+        });
+        Ok(())
+    }
+
+    pub fn created(&mut self, path: &Path, size: u64) -> io::Result<()> {
+        self.entry.actions.push(JournalAction::Created {
+            path: std::path::absolute(path)?,
+            size,
+        });
+        Ok(())
+    }
+
+    /// Write the entry out. Operations that changed nothing are not kept.
+    pub fn commit(self) -> io::Result<()> {
+        if self.entry.actions.is_empty() {
+            return fs::remove_dir_all(&self.dir);
+        }
+        let json = serde_json::to_string_pretty(&self.entry)?;
+        fs::write(self.dir.join("entry.json"), json)
+    }
+
+    pub fn discard(self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Open a journal record for `command` if journaling is enabled.
+fn begin_journal(enabled: bool, command: &str) -> Result<Option<JournalRecord>, &'static str> {
+    if !enabled {
+        return Ok(None);
+    }
+    Journal::open()
+        .and_then(|j| j.begin(command))
+        .map(Some)
+        .map_err(|_| "Could not open the undo journal, pass --no-journal to continue without it")
+}
+
+/// Write out `record`, then prune the journal to the configured retention.
+fn commit_journal(record: Option<JournalRecord>, log: &Logger) {
+    if let Some(r) = record {
+        let id = r.entry.id.clone();
+        let journal = Journal::at(r.dir.parent().unwrap_or(Path::new(".")));
+        if let Err(err) = r.commit() {
+            error!(log, "Failed to write the undo journal"; "error" => %err);
+            return;
+        }
+        let settings = Settings::load(log);
+        let dropped = journal.prune(settings.journal_max_entries, settings.journal_max_size, log);
+        if dropped.contains(&id) {
+            warn!(
+                log,
+                "The operation is larger than journal_max_size and cannot be undone";
+                "id" => &id
+            );
+        }
+        for d in dropped.iter().filter(|d| **d != id) {
+            info!(log, "Dropped an old operation from the undo journal"; "id" => d);
+        }
+    }
+}
+
+pub fn home_dir() -> io::Result<PathBuf> {
+    match std::env::var_os("HOME") {
+        Some(h) if !h.is_empty() => Ok(PathBuf::from(h)),
+        _ => Err(io::Error::new(io::ErrorKind::NotFound, "HOME is not set")),
+    }
+}
+
+/// Rename `from` to `to`, copying and deleting instead when they are on different filesystems.
+pub fn move_path(from: &Path, to: &Path, log: &Logger) -> io::Result<()> {
+    move_with(from, to, false, log)
+}
+
+/// Like `move_path`, but fails with `AlreadyExists` instead of replacing `to`.
+pub fn move_path_no_clobber(from: &Path, to: &Path, log: &Logger) -> io::Result<()> {
+    move_with(from, to, true, log)
+}
+
+fn move_with(from: &Path, to: &Path, no_clobber: bool, log: &Logger) -> io::Result<()> {
+    let renamed = if no_clobber {
+        rename_no_replace(from, to)
+    } else {
+        fs::rename(from, to)
+    };
+    match renamed {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            copy_and_remove(from, to, no_clobber, log)
+        }
+        res => res,
+    }
+}
+
+/// The fallback of `move_path` across filesystems: copy `from` to `to` with all
+/// metadata and delete `from` only once everything was copied. A failed copy
+/// leaves `from` alone and removes what it created.
+pub fn copy_and_remove(from: &Path, to: &Path, no_clobber: bool, log: &Logger) -> io::Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+    let opts = CopyOptions {
+        preserve: Preserve::parse("all").unwrap_or_default(),
+        ..CopyOptions::default()
+    };
+    if meta.is_dir() {
+        let existed = fs::symlink_metadata(to).is_ok();
+        if no_clobber && existed {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+        if let Err(err) = copy_path(from, to, &opts, &mut Progress::hidden(), log) {
+            if !existed {
+                let _ = fs::remove_dir_all(to);
+            }
+            return Err(err);
+        }
+        return fs::remove_dir_all(from);
+    }
+
+    // copy next to the destination first, so `to` is replaced in one step
+    let dir = match to.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let name = to.file_name().unwrap_or_default().to_string_lossy();
+    let (tmp_path, tmp) = create_temp_file(dir, &name)?;
+    drop(tmp);
+    let _ = fs::remove_file(&tmp_path);
+    let placed = copy_path(from, &tmp_path, &opts, &mut Progress::hidden(), log).and_then(|_| {
+        if no_clobber {
+            rename_no_replace(&tmp_path, to)
+        } else {
+            fs::rename(&tmp_path, to)
+        }
+    });
+    if let Err(err) = placed {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+    fs::remove_file(from)
+}
+
+/// `rename` that never replaces an existing `to`, atomically where the filesystem allows.
+fn rename_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+    let c_from = CString::new(from.as_os_str().as_bytes())?;
+    let c_to = CString::new(to.as_os_str().as_bytes())?;
+    // SAFETY: both paths are valid NUL terminated strings
+    let res = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            c_from.as_ptr(),
+            libc::AT_FDCWD,
+            c_to.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if res == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        // the filesystem doesn't support the flag, check by hand
+        Some(libc::EINVAL) | Some(libc::ENOSYS) => {
+            if fs::symlink_metadata(to).is_ok() {
+                Err(io::Error::from(io::ErrorKind::AlreadyExists))
+            } else {
+                fs::rename(from, to)
+            }
+        }
+        _ => Err(err),
+    }
+}
+/**************************** rust_undo ends *****************************/
+
+/**************************** rust_trash starts *****************************/
+
+pub struct TrashConfig<'a> {
+    pub action: &'a str,
+    pub names: Vec<&'a str>,
+    pub log: Logger,
+}
+
+pub fn run_trash(config: &TrashConfig) -> Result<(), &'static str> {
+    let trash = Trash::open().map_err(|_| "Could not open the trash")?;
+
+    match config.action {
+        "list" => {
+            let items = trash.list();
+            if items.is_empty() {
+                println!("Trash is empty");
+            }
+            for item in items {
+                println!("{}  {}  ({})", item.deleted, item.path.display(), item.name);
+            }
+        }
+        "restore" => {
+            let items = trash.list();
+            for n in &config.names {
+                // match the trash name or the original path, most recently deleted first
+                let abs = std::path::absolute(n).unwrap_or_else(|_| PathBuf::from(n));
+                let found = items
+                    .iter()
+                    .filter(|i| i.name == *n || i.path == abs)
+                    .max_by(|a, b| a.deleted.cmp(&b.deleted));
+                match found {
+                    Some(item) => match trash.restore(item, &config.log) {
+                        Ok(_) => {
+                            info!(config.log, "Restored"; "path" => %item.path.display());
+                            println!("Restored {}", item.path.display())
+                        }
+                        Err(err) => {
+                            error!(config.log, "Failed to restore"; "name" => n, "error" => %err)
+                        }
+                    },
+                    None => warn!(config.log, "Not in the trash"; "name" => n),
+                }
+            }
+        }
+        "empty" => {
+            let count = trash.empty().map_err(|_| "Failed to empty the trash")?;
+            println!("Permanently removed {} item(s)", count);
+        }
+        _ => return Err("Unknown trash action, expected list, restore or empty"),
+    }
+
+    Ok(())
+}
+
+impl<'a> TrashConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
+        let (action, sub) = args.subcommand().unwrap_or(("list", args));
+        let mut names: Vec<&'a str> = Vec::new();
+        if let Some(val) = sub.values_of("names") {
+            names = val.collect();
+        }
+
+        TrashConfig {
+            action,
+            names,
+            log: log.new(o!("command" => "trash")),
+        }
+    }
+}
+
+/// A file in the trash, read from its `.trashinfo` file.
+pub struct TrashItem {
+    /// Name under `files/`.
+    pub name: String,
+    /// Where the file was before it was trashed.
+    pub path: PathBuf,
+    /// Deletion date as written in the info file, `YYYY-MM-DDThh:mm:ss` local time.
+    pub deleted: String,
+    pub file: PathBuf,
+    pub info: PathBuf,
+}
+
+/// The home trash described by the FreeDesktop.org trash specification,
+/// `$XDG_DATA_HOME/Trash` (`~/.local/share/Trash` by default).
+pub struct Trash {
+    pub dir: PathBuf,
+}
+
+impl Trash {
+    pub fn open() -> io::Result<Self> {
+        let data = match std::env::var_os("XDG_DATA_HOME") {
+            Some(d) if !d.is_empty() => PathBuf::from(d),
+            _ => home_dir()?.join(".local/share"),
+        };
+        Trash::at(&data.join("Trash"))
+    }
+
+    pub fn at(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir.join("files"))?;
+        fs::create_dir_all(dir.join("info"))?;
+        Ok(Trash {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Move `path` into the trash, writing its `.trashinfo` first as the spec requires.
+    pub fn put(&self, path: &Path, log: &Logger) -> io::Result<TrashItem> {
+        fs::symlink_metadata(path)?;
+        let abs = std::path::absolute(path)?;
+        let base = abs
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "cannot trash this path"))?
+            .to_string_lossy()
+            .to_string();
+        let deleted = Local::now().format("%Y-%m-%dT%H:%M:%S").to_string();
+        let contents = format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            percent_encode_path(&abs),
+            deleted
+        );
+
+        // creating the info file exclusively reserves the name
+        let mut n = 1;
+        let (name, info, mut info_file) = loop {
+            let name = if n == 1 {
+                base.clone()
+            } else {
+                format!("{}.{}", base, n)
+            };
+            let info = self.dir.join("info").join(format!("{}.trashinfo", name));
+            match OpenOptions::new().write(true).create_new(true).open(&info) {
+                Ok(f) => break (name, info, f),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => n += 1,
+                Err(err) => return Err(err),
+            }
+        };
+        let file = self.dir.join("files").join(&name);
+        let res = info_file
+            .write_all(contents.as_bytes())
+            .and_then(|_| move_path(path, &file, log));
+        if let Err(err) = res {
+            let _ = fs::remove_file(&info);
+            return Err(err);
+        }
+
+        Ok(TrashItem {
+            name,
+            path: abs,
+            deleted,
+            file,
+            info,
+        })
+    }
+
+    pub fn list(&self) -> Vec<TrashItem> {
+        let mut res: Vec<TrashItem> = Vec::new();
+        if let Ok(readdir) = fs::read_dir(self.dir.join("info")) {
+            for entry in readdir.flatten() {
+                let info = entry.path();
+                let name = match info.file_name().and_then(|n| n.to_str()) {
+                    Some(n) => match n.strip_suffix(".trashinfo") {
+                        Some(stem) => stem.to_string(),
+                        None => continue,
+                    },
+                    None => continue,
+                };
+                if let Ok(contents) = fs::read_to_string(&info) {
+                    let mut path = None;
+                    let mut deleted = String::new();
+                    for line in contents.lines() {
+                        if let Some(p) = line.strip_prefix("Path=") {
+                            path = Some(percent_decode_path(p));
+                        } else if let Some(d) = line.strip_prefix("DeletionDate=") {
+                            deleted = d.to_string();
+                        }
+                    }
+                    if let Some(path) = path {
+                        res.push(TrashItem {
+                            file: self.dir.join("files").join(&name),
+                            name,
+                            path,
+                            deleted,
+                            info,
+                        });
+                    }
+                }
+            }
+        }
+        res.sort_by(|a, b| a.deleted.cmp(&b.deleted).then(a.name.cmp(&b.name)));
+        res
+    }
+
+    /// Move `item` back to where it was deleted from.
+    pub fn restore(&self, item: &TrashItem, log: &Logger) -> io::Result<()> {
+        if fs::symlink_metadata(&item.path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a file with that name already exists",
+            ));
+        }
+        if let Some(parent) = item.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_path(&item.file, &item.path, log)?;
+        fs::remove_file(&item.info)
+    }
+
+    /// Permanently delete everything in the trash, returning how many items were removed.
+    pub fn empty(&self) -> io::Result<usize> {
+        let mut count = 0;
+        for entry in fs::read_dir(self.dir.join("files"))?.flatten() {
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(&path)?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+            count += 1;
+        }
+        for entry in fs::read_dir(self.dir.join("info"))?.flatten() {
+            fs::remove_file(entry.path())?;
+        }
+        Ok(count)
+    }
+}
+
+/// Percent-encode an absolute path for the `Path=` key of a `.trashinfo` file.
+pub fn percent_encode_path(path: &Path) -> String {
+    let mut res = String::new();
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            res.push(b as char);
+        } else {
+            res.push_str(&format!("%{:02X}", b));
+        }
+    }
+    res
+}
+
+pub fn percent_decode_path(s: &str) -> PathBuf {
+    let bytes = s.as_bytes();
+    let mut res: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                res.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        res.push(bytes[i]);
+        i += 1;
+    }
+    PathBuf::from(OsString::from_vec(res))
+}
+/**************************** rust_trash ends *****************************/
+
+/**************************** rust_copy starts *****************************/
+
+pub struct CopyConfig<'a> {
+    pub sources: Vec<&'a str>,
+    pub dest: &'a str,
+    pub recursive: bool,
+    pub preserve: Option<&'a str>,
+    pub no_clobber: bool,
+    pub update: bool,
+    pub progress: bool,
+    pub log: Logger,
+}
+
+pub fn run_copy(config: &CopyConfig) -> Result<(), &'static str> {
+    let v_sources: Vec<PathBuf> = config.parse_sources()?;
+    let opts: CopyOptions = config.parse_options()?;
+    let dest = PathBuf::from(config.dest);
+    let into_dir = dest.is_dir();
+    if v_sources.len() > 1 && !into_dir {
+        return Err("Copying several sources needs an existing destination directory");
+    }
+
+    let total = v_sources.iter().map(|s| path_size(s)).sum();
+    let mut progress = Progress::new(total, config.progress || total >= PROGRESS_THRESHOLD);
+    let mut failed = false;
+
+    for src in v_sources {
+        let target = if into_dir {
+            match src.file_name() {
+                Some(name) => dest.join(name),
+                None => {
+                    warn!(config.log, "Cannot copy this path"; "path" => %src.display());
+                    continue;
+                }
+            }
+        } else {
+            dest.clone()
+        };
+        if src.is_dir() && !config.recursive {
+            warn!(config.log, "Not copying a directory without -r"; "path" => %src.display());
+            continue;
+        }
+        if src.is_dir() && is_inside(&target, &src) {
+            warn!(config.log, "Cannot copy a directory into itself"; "path" => %src.display());
+            continue;
+        }
+        match copy_path(&src, &target, &opts, &mut progress, &config.log) {
+            Ok(_) => {
+                info!(config.log, "Copied"; "from" => %src.display(), "to" => %target.display())
+            }
+            Err(err) => {
+                failed = true;
+                error!(config.log, "Failed to copy"; "path" => %src.display(), "error" => %err)
+            }
+        }
+    }
+    progress.finish();
+
+    if failed {
+        Err("Some files could not be copied")
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether `target` is `dir` or lies below it. Both are resolved first so
+/// spellings like `./src` and `src/sub` are compared properly. The last
+/// component of `target` and any parts that don't exist yet are kept as written.
+fn is_inside(target: &Path, dir: &Path) -> bool {
+    let dir = match fs::canonicalize(dir) {
+        Ok(d) => d,
+        Err(_) => return false,
+    };
+    let mut rest: Vec<&OsStr> = Vec::new();
+    let mut existing = target;
+    loop {
+        let (parent, name) = match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return false,
+        };
+        rest.push(name);
+        existing = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        if let Ok(base) = fs::canonicalize(existing) {
+            let resolved = rest.iter().rev().fold(base, |p, name| p.join(name));
+            return resolved.starts_with(&dir);
+        }
+    }
+}
+
+impl<'a> CopyConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
+        let sources: Vec<&'a str> = args.values_of("sources").unwrap().collect();
+        let dest: &'a str = args.value_of("dest").unwrap();
+        let recursive: bool = args.is_present("recursive");
+        let preserve: Option<&'a str> = args.value_of("preserve");
+        let no_clobber: bool = args.is_present("no-clobber");
+        let update: bool = args.is_present("update");
+        let progress: bool = args.is_present("progress");
+
+        CopyConfig {
+            sources,
+            dest,
+            recursive,
+            preserve,
+            no_clobber,
+            update,
+            progress,
+            log: log.new(o!("command" => "copy")),
+        }
+    }
+
+    pub fn parse_sources(&self) -> Result<Vec<PathBuf>, &'static str> {
+        let mut res: Vec<PathBuf> = Vec::new();
+        for s in &self.sources {
+            let src = PathBuf::from(s);
+            if fs::symlink_metadata(&src).is_ok() {
+                res.push(src);
+            } else {
+                warn!(self.log, "Source does not exist or is inaccessible, ignoring"; "path" => s);
+            }
+        }
+        if res.is_empty() {
+            Err("No valid sources given")
+        } else {
+            Ok(res)
+        }
+    }
+
+    pub fn parse_options(&self) -> Result<CopyOptions, &'static str> {
+        let preserve = match self.preserve {
+            Some(p) => Preserve::parse(p)?,
+            None => Preserve::default(),
+        };
+        Ok(CopyOptions {
+            preserve,
+            no_clobber: self.no_clobber,
+            update: self.update,
+        })
+    }
+}
+
+/// Metadata carried over by `--preserve`.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Preserve {
+    pub mode: bool,
+    pub timestamps: bool,
+    pub ownership: bool,
+    pub xattr: bool,
+}
+
+impl Preserve {
+    /// Parse a comma separated list of `mode`, `timestamps`, `ownership`, `xattr` or `all`.
+    pub fn parse(list: &str) -> Result<Self, &'static str> {
+        let mut res = Preserve::default();
+        for attr in list.split(',').map(|a| a.trim()) {
+            match attr {
+                "mode" => res.mode = true,
+                "timestamps" => res.timestamps = true,
+                "ownership" => res.ownership = true,
+                "xattr" => res.xattr = true,
+                "all" => {
+                    res = Preserve {
+                        mode: true,
+                        timestamps: true,
+                        ownership: true,
+                        xattr: true,
+                    }
+                }
+                _ => {
+                    return Err(
+                        "Invalid --preserve, expected a list of mode, timestamps, ownership, xattr or all",
+                    )
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CopyOptions {
+    pub preserve: Preserve,
+    /// Never overwrite an existing destination.
+    pub no_clobber: bool,
+    /// Only overwrite destinations older than the source.
+    pub update: bool,
+}
+
+/// Copy a file, symlink, FIFO, device or (recursively) a directory from `src`
+/// to `dst`. Entries of a directory that fail are logged and skipped, and the
+/// copy as a whole then fails once the rest is done.
+pub fn copy_path(
+    src: &Path,
+    dst: &Path,
+    opts: &CopyOptions,
+    progress: &mut Progress,
+    log: &Logger,
+) -> io::Result<()> {
+    let mut failed = 0;
+    copy_tree(src, dst, opts, progress, &mut failed, log)?;
+    if failed > 0 {
+        return Err(io::Error::other(format!(
+            "{} entries could not be copied",
+            failed
+        )));
+    }
+    Ok(())
+}
+
+fn copy_tree(
+    src: &Path,
+    dst: &Path,
+    opts: &CopyOptions,
+    progress: &mut Progress,
+    failed: &mut usize,
+    log: &Logger,
+) -> io::Result<()> {
+    let meta = fs::symlink_metadata(src)?;
+    let file_type = meta.file_type();
+    let existing = fs::symlink_metadata(dst).ok();
+
+    // opening the destination truncates it, which would wipe the source too,
+    // and a symlinked destination is opened through the link
+    let same = |m: &fs::Metadata| (m.dev(), m.ino()) == (meta.dev(), meta.ino());
+    if existing.as_ref().is_some_and(same) || fs::metadata(dst).is_ok_and(|m| same(&m)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} and {} are the same file", src.display(), dst.display()),
+        ));
+    }
+
+    if file_type.is_dir() {
+        let created = match existing {
+            Some(m) if m.is_dir() => false,
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a directory", dst.display()),
+                ))
+            }
+            // writable for now so a read-only directory can still be filled
+            None => {
+                fs::DirBuilder::new()
+                    .mode(meta.mode() & 0o777 | 0o700)
+                    .create(dst)?;
+                true
+            }
+        };
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            let target = dst.join(entry.file_name());
+            if let Err(err) = copy_tree(&entry.path(), &target, opts, progress, failed, log) {
+                *failed += 1;
+                error!(log, "Failed to copy"; "path" => %entry.path().display(), "error" => %err);
+            }
+        }
+        if created && meta.mode() & 0o700 != 0o700 {
+            let mode = fs::metadata(dst)?.mode() & 0o777 & !(0o700 & !meta.mode());
+            fs::set_permissions(dst, fs::Permissions::from_mode(mode))?;
+        }
+        // after the contents, so copying them doesn't bump the directory mtime again
+        return copy_metadata(src, dst, &meta, &opts.preserve, log);
+    }
+
+    if let Some(existing) = existing {
+        let newer = matches!(
+            (meta.modified(), existing.modified()),
+            (Ok(s), Ok(d)) if s > d
+        );
+        if opts.no_clobber || (opts.update && !newer) {
+            progress.advance(meta.len());
+            return Ok(());
+        }
+        if existing.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} is a directory", dst.display()),
+            ));
+        }
+    }
+
+    if file_type.is_symlink() {
+        let _ = fs::remove_file(dst);
+        std::os::unix::fs::symlink(fs::read_link(src)?, dst)?;
+        if opts.preserve.ownership {
+            let _ = std::os::unix::fs::lchown(dst, Some(meta.uid()), Some(meta.gid()));
+        }
+        return Ok(());
+    }
+
+    // FIFOs and devices are recreated, reading them could block forever
+    if !file_type.is_file() {
+        let _ = fs::remove_file(dst);
+        make_node(dst, &meta)?;
+        return copy_metadata(src, dst, &meta, &opts.preserve, log);
+    }
+
+    copy_file_contents(src, dst, &meta, progress)?;
+    copy_metadata(src, dst, &meta, &opts.preserve, log)
+}
+
+/// Create a FIFO, socket or device node at `path` like the one `meta` describes.
+/// Devices can only be created by root.
+fn make_node(path: &Path, meta: &fs::Metadata) -> io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mode = meta.mode() & (libc::S_IFMT | 0o777);
+    // SAFETY: the path is a valid NUL terminated string
+    let res = unsafe { libc::mknod(c_path.as_ptr(), mode, meta.rdev()) };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Copy the data of a regular file, cloning it with `FICLONE` when the
+/// filesystem supports reflinks and otherwise copying only the data extents so
+/// holes in sparse files stay holes.
+fn copy_file_contents(
+    src: &Path,
+    dst: &Path,
+    meta: &fs::Metadata,
+    progress: &mut Progress,
+) -> io::Result<()> {
+    let src_file = File::open(src)?;
+    let dst_file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(meta.mode() & 0o777)
+        .open(dst)?;
+
+    if ficlone(&src_file, &dst_file).is_ok() {
+        progress.advance(meta.len());
+        return Ok(());
+    }
+
+    let len = meta.len();
+    let mut buf = vec![0u8; 128 * 1024];
+    let mut pos: u64 = 0;
+    while pos < len {
+        // SAFETY: lseek only inspects the descriptor
+        let data =
+            unsafe { libc::lseek(src_file.as_raw_fd(), pos as libc::off_t, libc::SEEK_DATA) };
+        let (start, end) = if data < 0 {
+            match io::Error::last_os_error().raw_os_error() {
+                // no data after pos, the rest is a hole
+                Some(libc::ENXIO) => break,
+                // no hole support, copy everything that is left
+                _ => (pos, len),
+            }
+        } else {
+            // SAFETY: as above
+            let hole = unsafe { libc::lseek(src_file.as_raw_fd(), data, libc::SEEK_HOLE) };
+            let hole = if hole < 0 { len } else { hole as u64 };
+            (data as u64, hole.min(len))
+        };
+
+        let mut at = start;
+        while at < end {
+            let n = ((end - at) as usize).min(buf.len());
+            let read = src_file.read_at(&mut buf[..n], at)?;
+            if read == 0 {
+                break;
+            }
+            dst_file.write_all_at(&buf[..read], at)?;
+            at += read as u64;
+            progress.advance(read as u64);
+        }
+        progress.advance(start - pos);
+        pos = end.max(pos + 1);
+    }
+    dst_file.set_len(len)?;
+    Ok(())
+}
+
+/// Make `dst` share the data blocks of `src`, on filesystems with reflinks (btrfs, XFS, ...).
+fn ficlone(src: &File, dst: &File) -> io::Result<()> {
+    // SAFETY: both descriptors are valid for the duration of the call
+    let cloned = unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+    if cloned == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Create `dst` as a reflinked copy of `src`. Fails if the filesystem can't share the data.
+pub fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    let src_file = File::open(src)?;
+    let mode = src_file.metadata()?.mode() & 0o777;
+    let dst_file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(dst)?;
+    ficlone(&src_file, &dst_file).inspect_err(|_| {
+        let _ = fs::remove_file(dst);
+    })
+}
+
+fn copy_metadata(
+    src: &Path,
+    dst: &Path,
+    meta: &fs::Metadata,
+    preserve: &Preserve,
+    log: &Logger,
+) -> io::Result<()> {
+    let owned = fs::symlink_metadata(dst).map(|m| (m.uid(), m.gid()));
+    if preserve.ownership && owned.ok() != Some((meta.uid(), meta.gid())) {
+        // only root can give files away, so failing here is expected for other users
+        if let Err(err) = std::os::unix::fs::chown(dst, Some(meta.uid()), Some(meta.gid())) {
+            warn!(log, "Could not preserve ownership"; "path" => %dst.display(), "error" => %err);
+        }
+    }
+    if preserve.mode {
+        fs::set_permissions(dst, meta.permissions())?;
+    }
+    if preserve.xattr {
+        if let Ok(names) = xattr::list(src) {
+            for name in names {
+                if let Ok(Some(value)) = xattr::get(src, &name) {
+                    if let Err(err) = xattr::set(dst, &name, &value) {
+                        warn!(
+                            log,
+                            "Could not preserve extended attribute";
+                            "attribute" => %name.to_string_lossy(),
+                            "path" => %dst.display(),
+                            "error" => %err
+                        );
+                    }
+                }
+            }
+        }
+    }
+    if preserve.timestamps {
+        // by path, opening a FIFO would block
+        let times = TouchTimes {
+            accessed: Some(meta.accessed()?),
+            modified: Some(meta.modified()?),
+        };
+        set_path_times(dst, &times)?;
+    }
+    Ok(())
+}
+
+/// Trees at least this big get a progress bar even without `--progress`.
+const PROGRESS_THRESHOLD: u64 = 256 * 1024 * 1024;
+
+/// A progress bar on stderr, drawn only when stderr is a terminal.
+pub struct Progress {
+    total: u64,
+    done: u64,
+    enabled: bool,
+    last_draw: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new(total: u64, enabled: bool) -> Self {
+        Progress {
+            total,
+            done: 0,
+            enabled: enabled && io::stderr().is_terminal(),
+            last_draw: None,
+        }
+    }
+
+    /// A progress bar that never draws anything.
+    pub fn hidden() -> Self {
+        Progress::new(0, false)
+    }
+
+    pub fn advance(&mut self, bytes: u64) {
+        self.done += bytes;
+        if !self.enabled {
+            return;
+        }
+        // redrawing on every chunk would slow the copy down
+        if matches!(self.last_draw, Some(t) if t.elapsed() < Duration::from_millis(100)) {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+        self.draw();
+    }
+
+    fn draw(&self) {
+        const WIDTH: u64 = 30;
+        let done = self.done.min(self.total);
+        let filled = done
+            .checked_mul(WIDTH)
+            .and_then(|d| d.checked_div(self.total))
+            .unwrap_or(WIDTH);
+        let percent = done
+            .checked_mul(100)
+            .and_then(|d| d.checked_div(self.total))
+            .unwrap_or(100);
+        eprint!(
+            "\r[{}{}] {:>3}% {} / {}",
+            "=".repeat(filled as usize),
+            " ".repeat((WIDTH - filled) as usize),
+            percent,
+            human_size(done),
+            human_size(self.total)
+        );
+    }
+
+    pub fn finish(&mut self) {
+        if self.enabled && self.last_draw.is_some() {
+            self.done = self.total;
+            self.draw();
+            eprintln!();
+        }
+    }
+}
+/**************************** rust_copy ends *****************************/
+
+/**************************** rust_move starts *****************************/
+
+pub struct MoveConfig<'a> {
+    pub sources: Vec<&'a str>,
+    pub dest: &'a str,
+    pub no_clobber: bool,
+    pub backup: Option<&'a str>,
+    pub journal: bool,
+    pub log: Logger,
+}
+
+pub fn run_move(config: &MoveConfig) -> Result<(), &'static str> {
+    let v_sources: Vec<PathBuf> = config.parse_sources()?;
+    let dest = PathBuf::from(config.dest);
+    let into_dir = dest.is_dir();
+    if v_sources.len() > 1 && !into_dir {
+        return Err("Moving several sources needs an existing destination directory");
+    }
+    let mut record: Option<JournalRecord> = begin_journal(config.journal, "move")?;
+
+    for src in v_sources {
+        let target = if into_dir {
+            match src.file_name() {
+                Some(name) => dest.join(name),
+                None => {
+                    warn!(config.log, "Cannot move this path"; "path" => %src.display());
+                    continue;
+                }
+            }
+        } else {
+            dest.clone()
+        };
+        if src.is_dir() && is_inside(&target, &src) {
+            warn!(config.log, "Cannot move a directory into itself"; "path" => %src.display());
+            continue;
+        }
+
+        let mut backed_up: Option<PathBuf> = None;
+        if fs::symlink_metadata(&target).is_ok() {
+            if config.no_clobber {
+                warn!(
+                    config.log,
+                    "Destination already exists, not moving";
+                    "path" => %src.display(),
+                    "to" => %target.display()
+                );
+                continue;
+            }
+            if let Some(suffix) = config.backup {
+                let mut backup = target.clone().into_os_string();
+                backup.push(suffix);
+                let backup = PathBuf::from(backup);
+                if let Err(err) = move_path(&target, &backup, &config.log) {
+                    error!(
+                        config.log,
+                        "Could not back up";
+                        "path" => %target.display(),
+                        "error" => %err
+                    );
+                    continue;
+                }
+                backed_up = Some(backup);
+            }
+        }
+
+        let moved = if config.no_clobber {
+            move_path_no_clobber(&src, &target, &config.log)
+        } else {
+            move_path(&src, &target, &config.log)
+        };
+        match moved {
+            Ok(_) => {
+                info!(config.log, "Moved"; "from" => %src.display(), "to" => %target.display());
+                if let Some(r) = &mut record {
+                    if let Some(backup) = &backed_up {
+                        let _ = r.moved(&target, backup);
+                    }
+                    let _ = r.moved(&src, &target);
+                }
+            }
+            Err(err) => {
+                error!(config.log, "Failed to move"; "path" => %src.display(), "error" => %err);
+                // put the target back, it sits next to its backup so this is a plain rename
+                if let Some(backup) = &backed_up {
+                    if let Err(err) = fs::rename(backup, &target) {
+                        error!(
+                            config.log,
+                            "Could not restore the backup";
+                            "path" => %backup.display(),
+                            "error" => %err
+                        );
+                    }
+                }
+            }
+        }
+    }
+    commit_journal(record, &config.log);
+
+    Ok(())
+}
+
+impl<'a> MoveConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
+        let sources: Vec<&'a str> = args.values_of("sources").unwrap().collect();
+        let dest: &'a str = args.value_of("dest").unwrap();
+        let no_clobber: bool = args.is_present("no-clobber");
+        let backup: Option<&'a str> = args.value_of("backup");
+        let journal: bool = !args.is_present("no-journal");
+
+        MoveConfig {
+            sources,
+            dest,
+            no_clobber,
+            backup: backup.filter(|s| !s.is_empty()),
+            journal,
+            log: log.new(o!("command" => "move")),
+        }
+    }
+
+    pub fn parse_sources(&self) -> Result<Vec<PathBuf>, &'static str> {
+        let mut res: Vec<PathBuf> = Vec::new();
+        for s in &self.sources {
+            let src = PathBuf::from(s);
+            if fs::symlink_metadata(&src).is_ok() {
+                res.push(src);
+            } else {
+                warn!(self.log, "Source does not exist or is inaccessible, ignoring"; "path" => s);
+            }
+        }
+        if res.is_empty() {
+            Err("No valid sources given")
+        } else {
+            Ok(res)
+        }
+    }
+}
+/**************************** rust_move ends *****************************/
+
+/**************************** rust_rename starts *****************************/
+
+pub struct RenameConfig<'a> {
+    pub dirs: Vec<&'a str>,
+    /// Regexes selecting the files to rename, matched like `find --patterns`.
+    pub patterns: Vec<&'a str>,
+    /// Text to replace in the names and its replacement, like `tr --replace`.
+    pub replace: Vec<&'a str>,
+    pub recursive: bool,
+    pub case: Option<&'a str>,
+    pub start: Option<&'a str>,
+    pub dry_run: bool,
+    pub journal: bool,
+    pub log: Logger,
+}
+
+pub fn run_rename(config: &RenameConfig) -> Result<(), &'static str> {
+    let v_dirs: Vec<PathBuf> = config.parse_dirs()?;
+    let v_pats: Vec<Regex> = config.parse_patterns()?;
+    let replace: Option<Vec<&str>> = config.parse_replace()?;
+    let case: Option<NameCase> = config.parse_case()?;
+    let start: usize = config.parse_start()?;
+    if replace.is_none() && case.is_none() {
+        return Err("Nothing to do, give --replace or --case");
+    }
+
+    let mut found: Vec<MyFile> = Vec::new();
+    for d in &v_dirs {
+        get_matched_files(&mut found, d, &v_pats, None, false, &config.log);
+    }
+    // find always descends, without -R only the files directly in the directories count
+    let mut files: Vec<PathBuf> = found
+        .into_iter()
+        .map(|f| PathBuf::from(f.path))
+        .filter(|p| config.recursive || v_dirs.iter().any(|d| p.parent() == Some(d.as_path())))
+        .collect();
+    files.sort();
+
+    let plan = plan_renames(&files, replace.as_deref(), case, start);
+    if plan.is_empty() {
+        println!("Nothing to rename");
+        return Ok(());
+    }
+    print_renames(&plan);
+    check_renames(&plan, &config.log)?;
+    if config.dry_run {
+        return Ok(());
+    }
+
+    let mut record: Option<JournalRecord> = begin_journal(config.journal, "rename")?;
+    // undo can only put everything back if every rename made it into the journal
+    let mut undoable = record.is_some();
+    let mut res = Ok(());
+    for (from, to) in order_renames(&plan) {
+        if let Err(err) = rename_no_replace(&from, &to) {
+            error!(
+                config.log,
+                "Failed to rename";
+                "from" => %from.display(),
+                "to" => %to.display(),
+                "error" => %err
+            );
+            res = Err(if undoable {
+                "Renaming stopped part way, the renames done so far can be reverted with rust undo"
+            } else {
+                "Renaming stopped part way"
+            });
+            break;
+        }
+        info!(config.log, "Renamed"; "from" => %from.display(), "to" => %to.display());
+        if let Some(r) = &mut record {
+            if let Err(err) = r.moved(&from, &to) {
+                undoable = false;
+                error!(
+                    config.log,
+                    "Failed to record the rename in the undo journal";
+                    "from" => %from.display(),
+                    "error" => %err
+                );
+            }
+        }
+    }
+    commit_journal(record, &config.log);
+
+    res
+}
+
+impl<'a> RenameConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
+        let dirs: Vec<&'a str> = args.values_of("dirs").unwrap().collect();
+        let patterns: Vec<&'a str> = args.values_of("patterns").unwrap().collect();
+        let mut replace: Vec<&'a str> = Vec::new();
+        if let Some(val) = args.values_of("replace") {
+            replace = val.collect();
+        }
+        let recursive: bool = args.is_present("recursive");
+        let case: Option<&'a str> = args.value_of("case");
+        let start: Option<&'a str> = args.value_of("start");
+        let dry_run: bool = args.is_present("dry-run");
+        let journal: bool = !args.is_present("no-journal");
+
+        RenameConfig {
+            dirs,
+            patterns,
+            replace,
+            recursive,
+            case,
+            start,
+            dry_run,
+            journal,
+            log: log.new(o!("command" => "rename")),
+        }
+    }
+
+    pub fn parse_dirs(&self) -> Result<Vec<PathBuf>, &'static str> {
+        let mut res: Vec<PathBuf> = Vec::new();
+        let mut parsed = false;
+        for d in &self.dirs {
+            let dir = PathBuf::from(d);
+            if dir.is_dir() {
+                parsed = true;
+                res.push(dir);
+            } else {
+                warn!(self.log, "Invalid or inaccessible directory, ignoring"; "dir" => d);
+            }
+        }
+        if parsed {
+            Ok(res)
+        } else {
+            Err("No valid directories given")
+        }
+    }
+
+    pub fn parse_patterns(&self) -> Result<Vec<Regex>, &'static str> {
+        parse_regexes(&self.patterns, &self.log)
+    }
+
+    pub fn parse_replace(&self) -> Result<Option<Vec<&str>>, &'static str> {
+        match self.replace.as_slice() {
+            [] => Ok(None),
+            [from, to] if !from.is_empty() => Ok(Some(vec![from, to])),
+            _ => Err("Invalid --replace, expected the text to replace and its replacement"),
+        }
+    }
+
+    pub fn parse_case(&self) -> Result<Option<NameCase>, &'static str> {
+        match self.case {
+            Some(c) => NameCase::from_name(c)
+                .map(Some)
+                .ok_or("Invalid --case, expected lower, upper or title"),
+            None => Ok(None),
+        }
+    }
+
+    pub fn parse_start(&self) -> Result<usize, &'static str> {
+        match self.start {
+            Some(s) => s.parse().map_err(|_| "Invalid --start, expected a number"),
+            None => Ok(1),
+        }
+    }
+}
+
+/// Case transform applied to new file names, leaving the extension alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameCase {
+    Lower,
+    Upper,
+    /// First letter of every word upper case, the rest lower case.
+    Title,
+}
+
+impl NameCase {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "lower" => Some(NameCase::Lower),
+            "upper" => Some(NameCase::Upper),
+            "title" => Some(NameCase::Title),
+            _ => None,
+        }
+    }
+
+    pub fn apply(&self, name: &str) -> String {
+        // a leading dot starts a hidden name, not an extension
+        let (stem, ext) = match name.rfind('.') {
+            Some(i) if i > 0 => name.split_at(i),
+            _ => (name, ""),
+        };
+        let stem = match self {
+            NameCase::Lower => stem.to_lowercase(),
+            NameCase::Upper => stem.to_uppercase(),
+            NameCase::Title => {
+                let mut res = String::with_capacity(stem.len());
+                let mut word_start = true;
+                for c in stem.chars() {
+                    if word_start {
+                        res.extend(c.to_uppercase());
+                    } else {
+                        res.extend(c.to_lowercase());
+                    }
+                    word_start = !c.is_alphanumeric();
+                }
+                res
+            }
+        };
+        format!("{}{}", stem, ext)
+    }
+}
+
+/// Replace `{n}` counters in `replacement` with `n`. `{n:3}` pads with spaces
+/// and `{n:03}` with zeros to the given width.
+pub fn fill_counter(replacement: &str, n: usize) -> String {
+    static COUNTER: OnceLock<Regex> = OnceLock::new();
+    COUNTER
+        .get_or_init(|| Regex::new(r"\{n(?::(0)?(\d+))?\}").unwrap())
+        .replace_all(replacement, |caps: &regex::Captures| {
+            let width: usize = caps.get(2).map_or(0, |w| w.as_str().parse().unwrap_or(0));
+            if caps.get(1).is_some() {
+                format!("{:0width$}", n, width = width)
+            } else {
+                format!("{:width$}", n, width = width)
+            }
+        })
+        .into_owned()
+}
+
+/// Work out the new name of every file: `replace` swaps text in the name as
+/// `tr --replace` does in contents, with counters in the replacement numbered
+/// from `start` in the order given. Files that keep their name are left out.
+pub fn plan_renames(
+    files: &[PathBuf],
+    replace: Option<&[&str]>,
+    case: Option<NameCase>,
+    start: usize,
+) -> Vec<(PathBuf, PathBuf)> {
+    let mut res: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut n = start;
+    for f in files {
+        let name = match f.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        let mut new_name = name.to_string();
+        if let Some(r) = replace {
+            if !name.contains(r[0]) {
+                continue;
+            }
+            let to = fill_counter(r[1], n);
+            n += 1;
+            new_name = replace_words(&mut Some(new_name), vec![r[0], &to]).unwrap_or_default();
+        }
+        if let Some(c) = case {
+            new_name = c.apply(&new_name);
+        }
+        if new_name != name {
+            res.push((f.clone(), f.with_file_name(new_name)));
+        }
+    }
+    res
+}
+
+/// Check a rename plan before anything is touched: every new name must be a
+/// plain file name, unique, and not taken by a file that stays where it is.
+pub fn check_renames(plan: &[(PathBuf, PathBuf)], log: &Logger) -> Result<(), &'static str> {
+    let sources: HashSet<&PathBuf> = plan.iter().map(|(from, _)| from).collect();
+    let mut targets: HashMap<&PathBuf, &PathBuf> = HashMap::new();
+    let mut ok = true;
+    for (from, to) in plan {
+        let plain = to.parent() == from.parent()
+            && matches!(to.file_name(), Some(n) if n != "." && n != "..");
+        if !plain {
+            error!(log, "Not a valid file name"; "path" => %from.display(), "to" => %to.display());
+            ok = false;
+        } else if let Some(other) = targets.insert(to, from) {
+            error!(
+                log,
+                "Two files would be renamed to the same name";
+                "path" => %other.display(),
+                "other" => %from.display(),
+                "to" => %to.display()
+            );
+            ok = false;
+        } else if !sources.contains(to) && !same_file(from, to) && fs::symlink_metadata(to).is_ok()
+        {
+            error!(
+                log,
+                "Destination already exists";
+                "path" => %from.display(),
+                "to" => %to.display()
+            );
+            ok = false;
+        }
+    }
+    if ok {
+        Ok(())
+    } else {
+        Err("Conflicting renames, nothing was renamed")
+    }
+}
+
+/// Order a checked plan so no rename replaces a file that hasn't moved yet.
+/// Cycles such as a -> b, b -> a are broken by parking one file under a
+/// temporary name first.
+pub fn order_renames(plan: &[(PathBuf, PathBuf)]) -> Vec<(PathBuf, PathBuf)> {
+    let mut pending: Vec<(PathBuf, PathBuf)> = plan.to_vec();
+    let mut steps: Vec<(PathBuf, PathBuf)> = Vec::new();
+    while !pending.is_empty() {
+        let sources: HashSet<PathBuf> = pending.iter().map(|(from, _)| from.clone()).collect();
+        let (ready, blocked): (Vec<_>, Vec<_>) = pending
+            .into_iter()
+            .partition(|(_, to)| !sources.contains(to));
+        pending = blocked;
+        if ready.is_empty() {
+            let (from, to) = pending.remove(0);
+            let name = from.file_name().unwrap_or_default().to_string_lossy();
+            let tmp = from.with_file_name(format!(".{}.{}.rename", name, std::process::id()));
+            steps.push((from, tmp.clone()));
+            pending.push((tmp, to));
+        }
+        steps.extend(ready);
+    }
+    steps
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(x), Ok(y)) => x.dev() == y.dev() && x.ino() == y.ino(),
+        _ => false,
+    }
+}
+
+fn print_renames(plan: &[(PathBuf, PathBuf)]) {
+    let width = plan
+        .iter()
+        .map(|(from, _)| from.display().to_string().chars().count())
+        .max()
+        .unwrap_or(0);
+    for (from, to) in plan {
+        let new_name = to.file_name().unwrap_or_default().to_string_lossy();
+        println!(
+            "{:<width$}  ->  {}",
+            from.display().to_string(),
+            new_name,
+            width = width
+        );
+    }
+}
+/**************************** rust_rename ends *****************************/
+
+/**************************** rust_ls starts *****************************/
+
+pub struct LsConfig<'a> {
+    pub dirs: Vec<&'a str>,
+    pub long: bool,
+    pub human: bool,
+    pub sort: Option<&'a str>,
+    pub reverse: bool,
+    pub all: bool,
+    pub tree: bool,
+    pub depth: Option<&'a str>,
+    pub color: Option<&'a str>,
+    pub log: Logger,
+}
+
+pub fn run_ls(config: &LsConfig) -> Result<(), &'static str> {
+    let v_dirs: Vec<PathBuf> = config.parse_dirs()?;
+    let opts: LsOptions = config.parse_options()?;
+    let depth: Option<usize> = config.parse_depth()?;
+    let colors: Colors = parse_color(config.color)?;
+
+    for (i, d) in v_dirs.iter().enumerate() {
+        if v_dirs.len() > 1 && !config.tree {
+            if i > 0 {
+                println!();
+            }
+            println!("{}:", d.display());
+        }
+        let lines = if config.tree {
+            tree_lines(d, &opts, depth, &colors, &config.log)
+        } else {
+            ls_lines(d, &opts, &colors, &config.log)
+        };
+        for line in lines {
+            println!("{}", line);
+        }
+    }
+
+    Ok(())
+}
+
+impl<'a> LsConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
+        let dirs: Vec<&'a str> = match args.values_of("dirs") {
+            Some(d) => d.collect(),
+            None => vec!["."],
+        };
+        let long: bool = args.is_present("long");
+        let human: bool = args.is_present("human-readable");
+        let sort: Option<&'a str> = args.value_of("sort");
+        let reverse: bool = args.is_present("reverse");
+        let all: bool = args.is_present("all");
+        let tree: bool = args.is_present("tree");
+        let depth: Option<&'a str> = args.value_of("depth");
+        let color: Option<&'a str> = args.value_of("color");
+
+        LsConfig {
+            dirs,
+            long,
+            human,
+            sort,
+            reverse,
+            all,
+            tree,
+            depth,
+            color,
+            log: log.new(o!("command" => "ls")),
+        }
+    }
+
+    pub fn parse_dirs(&self) -> Result<Vec<PathBuf>, &'static str> {
+        let mut res: Vec<PathBuf> = Vec::new();
+        let mut parsed = false;
+        for d in &self.dirs {
+            let dir = PathBuf::from(d);
+            if dir.is_dir() {
+                parsed = true;
+                res.push(dir);
+            } else {
+                warn!(self.log, "Invalid or inaccessible directory, ignoring"; "dir" => d);
+            }
+        }
+        if parsed {
+            Ok(res)
+        } else {
+            Err("No valid directories given")
+        }
+    }
+
+    pub fn parse_options(&self) -> Result<LsOptions, &'static str> {
+        let sort = match self.sort {
+            Some(s) => LsSort::from_name(s).ok_or("Invalid --sort, expected name, size or time")?,
+            None => LsSort::Name,
+        };
+        Ok(LsOptions {
+            long: self.long,
+            human: self.human,
+            sort,
+            reverse: self.reverse,
+            all: self.all,
+        })
+    }
+
+    pub fn parse_depth(&self) -> Result<Option<usize>, &'static str> {
+        match self.depth {
+            Some(d) => d
+                .parse()
+                .map(Some)
+                .map_err(|_| "Invalid --depth, expected a number"),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LsSort {
+    Name,
+    /// Largest first.
+    Size,
+    /// Most recently modified first.
+    Time,
+}
+
+impl LsSort {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "name" => Some(LsSort::Name),
+            "size" => Some(LsSort::Size),
+            "time" => Some(LsSort::Time),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LsOptions {
+    pub long: bool,
+    pub human: bool,
+    pub sort: LsSort,
+    pub reverse: bool,
+    /// Include names starting with a dot.
+    pub all: bool,
+}
+
+/// The entries of `dir` in the order `opts` asks for.
+pub fn list_dir(dir: &Path, opts: &LsOptions, log: &Logger) -> Vec<MyFile> {
+    let mut res: Vec<MyFile> = Vec::new();
+    match fs::read_dir(dir) {
+        Ok(readdir) => {
+            for entry in readdir.flatten() {
+                let path = entry.path();
+                match MyFile::from_path_no_follow(&path) {
+                    Ok(f) if opts.all || !f.name.starts_with('.') => res.push(f),
+                    Ok(_) => {}
+                    Err(err) => {
+                        warn!(
+                            log,
+                            "Could not read entry";
+                            "path" => %path.display(),
+                            "error" => %err
+                        )
+                    }
+                }
+            }
+        }
+        Err(err) => {
+            warn!(
+                log,
+                "Could not read directory, skipping";
+                "dir" => %dir.display(),
+                "error" => %err
+            )
+        }
+    }
+    match opts.sort {
+        LsSort::Name => res.sort_by(|a, b| a.name.cmp(&b.name)),
+        LsSort::Size => {
+            res.sort_by(|a, b| b.size_bytes.cmp(&a.size_bytes).then(a.name.cmp(&b.name)))
+        }
+        LsSort::Time => res.sort_by(|a, b| b.modified.cmp(&a.modified).then(a.name.cmp(&b.name))),
+    }
+    if opts.reverse {
+        res.reverse();
+    }
+    res
+}
+
+/// One line per entry of `dir`, in long format if `opts.long` is set.
+pub fn ls_lines(dir: &Path, opts: &LsOptions, colors: &Colors, log: &Logger) -> Vec<String> {
+    let files = list_dir(dir, opts, log);
+    if !opts.long {
+        return files.iter().map(|f| entry_name(f, colors)).collect();
+    }
+
+    let mut names = OwnerNames::default();
+    let rows: Vec<[String; 5]> = files
+        .iter()
+        .map(|f| {
+            [
+                f.mode_string(),
+                names.user(f.uid),
+                names.group(f.gid),
+                format_size(f.size_bytes, opts.human),
+                format_mtime(f.modified),
+            ]
+        })
+        .collect();
+    let width = |col: usize| {
+        rows.iter()
+            .map(|r| r[col].chars().count())
+            .max()
+            .unwrap_or(0)
+    };
+    let (user_w, group_w, size_w) = (width(1), width(2), width(3));
+    files
+        .iter()
+        .zip(rows.iter())
+        .map(|(f, r)| {
+            format!(
+                "{} {:<user_w$} {:<group_w$} {:>size_w$} {} {}",
+                r[0],
+                r[1],
+                r[2],
+                r[3],
+                r[4],
+                entry_name(f, colors),
+                user_w = user_w,
+                group_w = group_w,
+                size_w = size_w
+            )
+        })
+        .collect()
+}
+
+/// `dir` and everything below it drawn as a tree, down to `depth` levels.
+pub fn tree_lines(
+    dir: &Path,
+    opts: &LsOptions,
+    depth: Option<usize>,
+    colors: &Colors,
+    log: &Logger,
+) -> Vec<String> {
+    let mut lines: Vec<String> = vec![colors.paint(
+        colors.ls_code(libc::S_IFDIR, ""),
+        &dir.display().to_string(),
+    )];
+    let mut names = OwnerNames::default();
+    let mut counts = (0, 0);
+    tree_walk(
+        dir,
+        "",
+        0,
+        opts,
+        depth,
+        colors,
+        log,
+        &mut names,
+        &mut counts,
+        &mut lines,
+    );
+    lines.push(String::new());
+    lines.push(format!(
+        "{} {}, {} {}",
+        counts.0,
+        if counts.0 == 1 {
+            "directory"
+        } else {
+            "directories"
+        },
+        counts.1,
+        if counts.1 == 1 { "file" } else { "files" }
+    ));
+    lines
+}
+
+#[allow(clippy::too_many_arguments)]
+fn tree_walk(
+    dir: &Path,
+    prefix: &str,
+    level: usize,
+    opts: &LsOptions,
+    depth: Option<usize>,
+    colors: &Colors,
+    log: &Logger,
+    names: &mut OwnerNames,
+    counts: &mut (usize, usize),
+    lines: &mut Vec<String>,
+) {
+    if matches!(depth, Some(d) if level >= d) {
+        return;
+    }
+    let files = list_dir(dir, opts, log);
+    for (i, f) in files.iter().enumerate() {
+        let last = i + 1 == files.len();
+        let details = if opts.long {
+            format!(
+                "[{} {} {:>10} {}] ",
+                f.mode_string(),
+                names.user(f.uid),
+                format_size(f.size_bytes, opts.human),
+                format_mtime(f.modified)
+            )
+        } else if opts.human {
+            format!("[{:>10}] ", format_size(f.size_bytes, true))
+        } else {
+            String::new()
+        };
+        let branch = if last { "└── " } else { "├── " };
+        lines.push(format!(
+            "{}{}{}{}",
+            prefix,
+            branch,
+            details,
+            entry_name(f, colors)
+        ));
+
+        if f.is_dir() {
+            counts.0 += 1;
+            let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            tree_walk(
+                Path::new(&f.path),
+                &child_prefix,
+                level + 1,
+                opts,
+                depth,
+                colors,
+                log,
+                names,
+                counts,
+                lines,
+            );
+        } else {
+            counts.1 += 1;
+        }
+    }
+}
+
+fn entry_name(f: &MyFile, colors: &Colors) -> String {
+    let name = colors.file(f, &f.name);
+    if f.is_symlink() {
+        match fs::read_link(&f.path) {
+            Ok(target) => format!("{} -> {}", name, target.display()),
+            Err(_) => name,
+        }
+    } else if f.is_dir() {
+        format!("{}/", name)
+    } else {
+        name
+    }
+}
+
+fn format_size(bytes: u64, human: bool) -> String {
+    if human {
+        human_size(bytes)
+    } else {
+        bytes.to_string()
+    }
+}
+
+fn format_mtime(time: SystemTime) -> String {
+    DateTime::<Local>::from(time)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+/// User and group names looked up once per id.
+#[derive(Default)]
+struct OwnerNames {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl OwnerNames {
+    fn user(&mut self, uid: u32) -> String {
+        self.users
+            .entry(uid)
+            .or_insert_with(|| {
+                let mut buf = vec![0 as libc::c_char; 4096];
+                // SAFETY: passwd is plain data and is only read when the lookup succeeded
+                unsafe {
+                    let mut pwd: libc::passwd = std::mem::zeroed();
+                    let mut found: *mut libc::passwd = std::ptr::null_mut();
+                    let rc =
+                        libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut found);
+                    if rc == 0 && !found.is_null() {
+                        return CStr::from_ptr(pwd.pw_name).to_string_lossy().into_owned();
+                    }
+                }
+                uid.to_string()
+            })
+            .clone()
+    }
+
+    fn group(&mut self, gid: u32) -> String {
+        self.groups
+            .entry(gid)
+            .or_insert_with(|| {
+                let mut buf = vec![0 as libc::c_char; 4096];
+                // SAFETY: as above
+                unsafe {
+                    let mut grp: libc::group = std::mem::zeroed();
+                    let mut found: *mut libc::group = std::ptr::null_mut();
+                    let rc =
+                        libc::getgrgid_r(gid, &mut grp, buf.as_mut_ptr(), buf.len(), &mut found);
+                    if rc == 0 && !found.is_null() {
+                        return CStr::from_ptr(grp.gr_name).to_string_lossy().into_owned();
+                    }
+                }
+                gid.to_string()
+            })
+            .clone()
+    }
+}
+/**************************** rust_ls ends *****************************/
+
+/**************************** rust_du starts *****************************/
+
+pub struct DuConfig<'a> {
+    pub dirs: Vec<&'a str>,
+    pub max_depth: Option<&'a str>,
+    pub top: Option<&'a str>,
+    pub threshold: Option<&'a str>,
+    pub apparent: bool,
+    pub human: bool,
+    pub all: bool,
+    pub threads: Option<&'a str>,
+    pub log: Logger,
+}
+
+pub fn run_du(config: &DuConfig) -> Result<(), &'static str> {
+    let v_dirs: Vec<PathBuf> = config.parse_dirs()?;
+    let opts: DuOptions = config.parse_options()?;
+    let pool = config.parse_threads()?;
+
+    for d in v_dirs {
+        let tree = pool.install(|| disk_usage(&d, opts.all || opts.top.is_some(), &config.log));
+        for line in du_lines(&tree, &opts) {
+            println!("{}", line);
+        }
+    }
+
+    Ok(())
+}
+
+impl<'a> DuConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
+        let dirs: Vec<&'a str> = match args.values_of("dirs") {
+            Some(d) => d.collect(),
+            None => vec!["."],
+        };
+        let max_depth: Option<&'a str> = args.value_of("max-depth");
+        let top: Option<&'a str> = args.value_of("top");
+        let threshold: Option<&'a str> = args.value_of("threshold");
+        let apparent: bool = args.is_present("apparent-size");
+        let human: bool = args.is_present("human-readable");
+        let all: bool = args.is_present("all");
+        let threads: Option<&'a str> = args.value_of("threads");
+
+        DuConfig {
+            dirs,
+            max_depth,
+            top,
+            threshold,
+            apparent,
+            human,
+            all,
+            threads,
+            log: log.new(o!("command" => "du")),
+        }
+    }
+
+    pub fn parse_dirs(&self) -> Result<Vec<PathBuf>, &'static str> {
+        let mut res: Vec<PathBuf> = Vec::new();
+        let mut parsed = false;
+        for d in &self.dirs {
+            let dir = PathBuf::from(d);
+            if dir.is_dir() {
+                parsed = true;
+                res.push(dir);
+            } else {
+                warn!(self.log, "Invalid or inaccessible directory, ignoring"; "dir" => d);
+            }
+        }
+        if parsed {
+            Ok(res)
+        } else {
+            Err("No valid directories given")
+        }
+    }
+
+    pub fn parse_options(&self) -> Result<DuOptions, &'static str> {
+        let max_depth = match self.max_depth {
+            Some(d) => Some(
+                d.parse()
+                    .map_err(|_| "Invalid --max-depth, expected a number")?,
+            ),
+            None => None,
+        };
+        let top = match self.top {
+            Some(n) => Some(n.parse().map_err(|_| "Invalid --top, expected a number")?),
+            None => None,
+        };
+        let threshold = match self.threshold {
+            Some(t) => {
+                Some(parse_human_size(t).ok_or("Invalid --threshold, expected a size like 100M")?)
+            }
+            None => None,
+        };
+        Ok(DuOptions {
+            max_depth,
+            top,
+            threshold,
+            apparent: self.apparent,
+            human: self.human,
+            all: self.all,
+        })
+    }
+
+    pub fn parse_threads(&self) -> Result<rayon::ThreadPool, &'static str> {
+        let threads: usize = match self.threads {
+            Some(t) => t
+                .parse()
+                .map_err(|_| "Invalid --threads, expected a number")?,
+            // rayon picks one per core
+            None => 0,
+        };
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|_| "Could not start the worker threads")
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DuOptions {
+    /// Only report entries this many levels below the starting directory.
+    pub max_depth: Option<usize>,
+    /// Report the N largest entries instead of every directory.
+    pub top: Option<usize>,
+    /// Leave out entries smaller than this many bytes.
+    pub threshold: Option<u64>,
+    /// Sort and filter by apparent size rather than allocated size.
+    pub apparent: bool,
+    pub human: bool,
+    /// Report files as well as directories.
+    pub all: bool,
+}
+
+/// Sizes of a directory (everything below it included) or a file.
+pub struct DuNode {
+    pub path: PathBuf,
+    /// Sum of file lengths.
+    pub apparent: u64,
+    /// Bytes actually allocated on disk.
+    pub allocated: u64,
+    pub is_dir: bool,
+    pub children: Vec<DuNode>,
+}
+
+/// Add up the sizes below `dir`, walking subdirectories in parallel. Files
+/// with several hard links are counted once. Files are only kept as nodes
+/// of their own when `keep_files` is set.
+pub fn disk_usage(dir: &Path, keep_files: bool, log: &Logger) -> DuNode {
+    let (apparent, allocated) = match fs::symlink_metadata(dir) {
+        Ok(m) => (m.len(), m.blocks() * 512),
+        Err(_) => (0, 0),
+    };
+    let seen: Mutex<HashSet<(u64, u64)>> = Mutex::new(HashSet::new());
+    du_walk(dir, apparent, allocated, keep_files, &seen, log)
+}
+
+fn du_walk(
+    dir: &Path,
+    apparent: u64,
+    allocated: u64,
+    keep_files: bool,
+    seen: &Mutex<HashSet<(u64, u64)>>,
+    log: &Logger,
+) -> DuNode {
+    let mut node = DuNode {
+        path: dir.to_path_buf(),
+        apparent,
+        allocated,
+        is_dir: true,
+        children: Vec::new(),
+    };
+    // the real paths, `MyFile::path` is lossy for names that aren't UTF-8
+    let mut subdirs: Vec<(PathBuf, MyFile)> = Vec::new();
+    match fs::read_dir(dir) {
+        Ok(readdir) => {
+            for entry in readdir.flatten() {
+                let path = entry.path();
+                let f = match MyFile::from_path_no_follow(&path) {
+                    Ok(f) => f,
+                    Err(err) => {
+                        warn!(
+                            log,
+                            "Could not read entry";
+                            "path" => %path.display(),
+                            "error" => %err
+                        );
+                        continue;
+                    }
+                };
+                if f.is_dir() {
+                    subdirs.push((path, f));
+                    continue;
+                }
+                if f.nlink > 1 && !seen.lock().unwrap().insert((f.dev, f.ino)) {
+                    continue;
+                }
+                node.apparent += f.size_bytes;
+                node.allocated += f.blocks * 512;
+                if keep_files {
+                    node.children.push(DuNode {
+                        path,
+                        apparent: f.size_bytes,
+                        allocated: f.blocks * 512,
+                        is_dir: false,
+                        children: Vec::new(),
+                    });
+                }
+            }
+        }
+        Err(err) => {
+            warn!(
+                log,
+                "Could not read directory, skipping";
+                "dir" => %dir.display(),
+                "error" => %err
+            )
+        }
+    }
+
+    let walked: Vec<DuNode> = subdirs
+        .par_iter()
+        .map(|(path, d)| du_walk(path, d.size_bytes, d.blocks * 512, keep_files, seen, log))
+        .collect();
+    for sub in walked {
+        node.apparent += sub.apparent;
+        node.allocated += sub.allocated;
+        node.children.push(sub);
+    }
+    node.children.sort_by(|a, b| a.path.cmp(&b.path));
+    node
+}
+
+/// The report for `tree`: one line per entry with the allocated size, the
+/// apparent size and the path, children before their parent like `du`, or
+/// the largest entries first with `--top`.
+pub fn du_lines(tree: &DuNode, opts: &DuOptions) -> Vec<String> {
+    let mut entries: Vec<&DuNode> = Vec::new();
+    du_collect(tree, 0, opts, &mut entries);
+    let size = |n: &DuNode| {
+        if opts.apparent {
+            n.apparent
+        } else {
+            n.allocated
+        }
+    };
+    if let Some(threshold) = opts.threshold {
+        entries.retain(|n| size(n) >= threshold);
+    }
+    if let Some(top) = opts.top {
+        // the starting directory would always come first
+        entries.retain(|n| !std::ptr::eq(*n, tree));
+        entries.sort_by(|a, b| size(b).cmp(&size(a)).then(a.path.cmp(&b.path)));
+        entries.truncate(top);
+    }
+
+    let rows: Vec<(String, String, String)> = entries
+        .iter()
+        .map(|n| {
+            (
+                format_size(n.allocated, opts.human),
+                format_size(n.apparent, opts.human),
+                n.path.display().to_string(),
+            )
+        })
+        .collect();
+    let alloc_w = rows.iter().map(|r| r.0.len()).max().unwrap_or(0);
+    let app_w = rows.iter().map(|r| r.1.len()).max().unwrap_or(0);
+    rows.into_iter()
+        .map(|(alloc, app, path)| {
+            format!(
+                "{:>alloc_w$}  {:>app_w$}  {}",
+                alloc,
+                app,
+                path,
+                alloc_w = alloc_w,
+                app_w = app_w
+            )
+        })
+        .collect()
+}
+
+fn du_collect<'t>(node: &'t DuNode, depth: usize, opts: &DuOptions, out: &mut Vec<&'t DuNode>) {
+    if matches!(opts.max_depth, Some(max) if depth > max) {
+        return;
+    }
+    for child in &node.children {
+        du_collect(child, depth + 1, opts, out);
+    }
+    if node.is_dir || opts.all || opts.top.is_some() {
+        out.push(node);
+    }
+}
+
+/// Parse a size like `1500`, `10K`, `1.5M`, `2GiB` or `1T`. Units are binary.
+pub fn parse_human_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (num, unit) = size.split_at(split);
+    let num: f64 = num.parse().ok()?;
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        _ => return None,
+    };
+    Some((num * (1u64 << shift) as f64) as u64)
+}
+/**************************** rust_du ends *****************************/
+
+/**************************** rust_dupes starts *****************************/
+
+pub struct DupesConfig<'a> {
+    pub dirs: Vec<&'a str>,
+    pub patterns: Vec<&'a str>,
+    pub min_size: Option<&'a str>,
+    pub action: Option<&'a str>,
+    pub apply: bool,
+    pub json: bool,
+    pub journal: bool,
+    pub log: Logger,
+}
+
+pub fn run_dupes(config: &DupesConfig) -> Result<(), &'static str> {
+    let v_dirs: Vec<PathBuf> = config.parse_dirs()?;
+    let v_pats: Vec<Regex> = config.parse_patterns()?;
+    let min_size: u64 = config.parse_min_size()?;
+    let action: Option<DupeAction> = config.parse_action()?;
+
+    let mut files: Vec<MyFile> = Vec::new();
+    for d in &v_dirs {
+        let mut found: Vec<MyFile> = Vec::new();
+        get_matched_files(&mut found, d, &v_pats, None, false, &config.log);
+        found.sort_by(|a, b| a.path.cmp(&b.path));
+        files.extend(found);
+    }
+    let sets = find_duplicates(files, min_size, &config.log);
+
+    if config.json {
+        let report = DupeReport {
+            wasted_bytes: sets.iter().map(|s| s.wasted()).sum(),
+            sets: &sets,
+        };
+        let json =
+            serde_json::to_string_pretty(&report).map_err(|_| "Could not write the report")?;
+        println!("{}", json);
+    } else {
+        for set in &sets {
+            println!(
+                "{} copies of {} ({}):",
+                set.files.len(),
+                human_size(set.size),
+                &set.hash[..16]
+            );
+            for f in &set.files {
+                println!("  {}", f.display());
+            }
+        }
+        println!(
+            "{} duplicate sets, {} could be freed",
+            sets.len(),
+            human_size(sets.iter().map(|s| s.wasted()).sum())
+        );
+    }
+
+    let action = match action {
+        Some(a) => a,
+        None => return Ok(()),
+    };
+    let mut record: Option<JournalRecord> = if config.apply {
+        begin_journal(config.journal, "dupes")?
+    } else {
+        None
+    };
+    for set in &sets {
+        let keep = &set.files[0];
+        for dup in &set.files[1..] {
+            if !config.apply {
+                // keep stdout clean for --json
+                eprintln!("Would {} {}", action.describe(keep), dup.display());
+                continue;
+            }
+            match apply_dupe_action(action, keep, dup, set.size, &mut record, &config.log) {
+                Ok(_) => info!(
+                    config.log,
+                    "Handled duplicate";
+                    "action" => action.describe(keep),
+                    "path" => %dup.display()
+                ),
+                Err(err) => {
+                    error!(
+                        config.log,
+                        "Failed to handle duplicate";
+                        "action" => action.describe(keep),
+                        "path" => %dup.display(),
+                        "error" => %err
+                    );
+                }
+            }
+        }
+    }
+    if !config.apply && !sets.is_empty() {
+        eprintln!("Nothing was changed, pass --apply to do this");
+    }
+    commit_journal(record, &config.log);
+
+    Ok(())
+}
+
+impl<'a> DupesConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
+        let dirs: Vec<&'a str> = args.values_of("dirs").unwrap().collect();
+        let patterns: Vec<&'a str> = match args.values_of("patterns") {
+            Some(p) => p.collect(),
+            None => Vec::new(),
+        };
+        let min_size: Option<&'a str> = args.value_of("min-size");
+        let action: Option<&'a str> = if args.is_present("delete-keep-first") {
+            Some("delete")
+        } else if args.is_present("hardlink") {
+            Some("hardlink")
+        } else if args.is_present("reflink") {
+            Some("reflink")
+        } else {
+            None
+        };
+        let apply: bool = args.is_present("apply");
+        let json: bool = args.is_present("json");
+        let journal: bool = !args.is_present("no-journal");
+
+        DupesConfig {
+            dirs,
+            patterns,
+            min_size,
+            action,
+            apply,
+            json,
+            journal,
+            log: log.new(o!("command" => "dupes")),
+        }
+    }
+
+    pub fn parse_dirs(&self) -> Result<Vec<PathBuf>, &'static str> {
+        let mut res: Vec<PathBuf> = Vec::new();
+        let mut parsed = false;
+        for d in &self.dirs {
+            let dir = PathBuf::from(d);
+            if dir.is_dir() {
+                parsed = true;
+                res.push(dir);
+            } else {
+                warn!(self.log, "Invalid or inaccessible directory, ignoring"; "dir" => d);
+            }
+        }
+        if parsed {
+            Ok(res)
+        } else {
+            Err("No valid directories given")
+        }
+    }
+
+    /// The find-style patterns, or one matching every file if none were given.
+    pub fn parse_patterns(&self) -> Result<Vec<Regex>, &'static str> {
+        if self.patterns.is_empty() {
+            parse_regexes(&[""], &self.log)
+        } else {
+            parse_regexes(&self.patterns, &self.log)
+        }
+    }
+
+    pub fn parse_min_size(&self) -> Result<u64, &'static str> {
+        match self.min_size {
+            Some(s) => parse_human_size(s).ok_or("Invalid --min-size, expected a size like 10K"),
+            // empty files are all the same, but there is nothing to gain from them
+            None => Ok(1),
+        }
+    }
+
+    pub fn parse_action(&self) -> Result<Option<DupeAction>, &'static str> {
+        match self.action {
+            Some("delete") => Ok(Some(DupeAction::Delete)),
+            Some("hardlink") => Ok(Some(DupeAction::Hardlink)),
+            Some("reflink") => Ok(Some(DupeAction::Reflink)),
+            Some(_) => Err("Invalid action, expected delete, hardlink or reflink"),
+            None => Ok(None),
+        }
+    }
+}
+
+/// What to do with every file of a set except the first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DupeAction {
+    Delete,
+    /// Replace it with a hard link to the first file.
+    Hardlink,
+    /// Replace it with a copy sharing the first file's data blocks.
+    Reflink,
+}
+
+impl DupeAction {
+    fn describe(&self, keep: &Path) -> String {
+        match self {
+            DupeAction::Delete => "delete".to_string(),
+            DupeAction::Hardlink => format!("hard link {} to", keep.display()),
+            DupeAction::Reflink => format!("reflink {} to", keep.display()),
+        }
+    }
+}
+
+/// Files with identical contents, in the order they were found.
+#[derive(Serialize, Debug)]
+pub struct DupeSet {
+    pub size: u64,
+    /// BLAKE3 hash of the contents, in hex.
+    pub hash: String,
+    pub files: Vec<PathBuf>,
+}
+
+impl DupeSet {
+    /// Bytes freed by keeping only one of the files.
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.files.len() as u64 - 1)
+    }
+}
+
+#[derive(Serialize)]
+struct DupeReport<'s> {
+    wasted_bytes: u64,
+    sets: &'s [DupeSet],
+}
+
+/// Find sets of files with the same contents. Candidates are narrowed down by
+/// size, then by a hash of their first and last blocks, and only then hashed
+/// in full. Symlinks and extra hard links to an inode already seen are skipped.
+pub fn find_duplicates(files: Vec<MyFile>, min_size: u64, log: &Logger) -> Vec<DupeSet> {
+    let mut inodes: HashSet<(u64, u64)> = HashSet::new();
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    let mut sizes: Vec<u64> = Vec::new();
+    for f in files {
+        let path = PathBuf::from(&f.path);
+        let is_link = fs::symlink_metadata(&path)
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(true);
+        if is_link || f.size_bytes < min_size || !inodes.insert((f.dev, f.ino)) {
+            continue;
+        }
+        by_size
+            .entry(f.size_bytes)
+            .or_insert_with(|| {
+                sizes.push(f.size_bytes);
+                Vec::new()
+            })
+            .push(path);
+    }
+
+    let mut res: Vec<DupeSet> = Vec::new();
+    for size in sizes {
+        let same_size = by_size.remove(&size).unwrap_or_default();
+        if same_size.len() < 2 {
+            continue;
+        }
+        for candidates in group_by_hash(same_size, |p| partial_hash(p, size), log) {
+            for (hash, set) in group_by_hash_keyed(candidates, full_hash, log) {
+                res.push(DupeSet {
+                    size,
+                    hash,
+                    files: set,
+                });
+            }
+        }
+    }
+    res
+}
+
+fn group_by_hash<F>(paths: Vec<PathBuf>, hash: F, log: &Logger) -> Vec<Vec<PathBuf>>
+where
+    F: Fn(&Path) -> io::Result<String> + Sync,
+{
+    group_by_hash_keyed(paths, hash, log)
+        .into_iter()
+        .map(|(_, group)| group)
+        .collect()
+}
+
+/// Hash `paths` in parallel and keep the groups of two or more with the same hash,
+/// preserving the order of `paths`.
+fn group_by_hash_keyed<F>(paths: Vec<PathBuf>, hash: F, log: &Logger) -> Vec<(String, Vec<PathBuf>)>
+where
+    F: Fn(&Path) -> io::Result<String> + Sync,
+{
+    let hashes: Vec<io::Result<String>> = paths.par_iter().map(|p| hash(p)).collect();
+    let mut groups: Vec<(String, Vec<PathBuf>)> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (path, h) in paths.into_iter().zip(hashes) {
+        let h = match h {
+            Ok(h) => h,
+            Err(err) => {
+                warn!(
+                    log,
+                    "Could not read file, skipping";
+                    "path" => %path.display(),
+                    "error" => %err
+                );
+                continue;
+            }
+        };
+        match index.get(&h) {
+            Some(&i) => groups[i].1.push(path),
+            None => {
+                index.insert(h.clone(), groups.len());
+                groups.push((h, vec![path]));
+            }
+        }
+    }
+    groups.retain(|(_, group)| group.len() > 1);
+    groups
+}
+
+/// Size of the blocks read from each end of a file for the partial hash.
+const PARTIAL_BLOCK: u64 = 4096;
+
+fn partial_hash(path: &Path, size: u64) -> io::Result<String> {
+    let file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0u8; PARTIAL_BLOCK as usize];
+    let head = size.min(PARTIAL_BLOCK) as usize;
+    file.read_exact_at(&mut buf[..head], 0)?;
+    hasher.update(&buf[..head]);
+    if size > PARTIAL_BLOCK {
+        let start = (size - PARTIAL_BLOCK).max(PARTIAL_BLOCK);
+        let tail = (size - start) as usize;
+        file.read_exact_at(&mut buf[..tail], start)?;
+        hasher.update(&buf[..tail]);
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+fn full_hash(path: &Path) -> io::Result<String> {
+    hash_file(path, HashAlgorithm::Blake3)
+}
+
+fn apply_dupe_action(
+    action: DupeAction,
+    keep: &Path,
+    dup: &Path,
+    size: u64,
+    record: &mut Option<JournalRecord>,
+    log: &Logger,
+) -> io::Result<()> {
+    if action == DupeAction::Delete {
+        return match record {
+            Some(r) => r.move_aside(dup, log),
+            None => fs::remove_file(dup),
+        };
+    }
+
+    // build the replacement next to the duplicate, then swap it in
+    let dir = match dup.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let name = dup.file_name().unwrap_or_default().to_string_lossy();
+    let (tmp_path, tmp) = create_temp_file(dir, &name)?;
+    drop(tmp);
+    fs::remove_file(&tmp_path)?;
+    match action {
+        DupeAction::Hardlink => fs::hard_link(keep, &tmp_path)?,
+        _ => reflink(keep, &tmp_path)?,
+    }
+
+    let swapped = match record {
+        Some(r) => r
+            .move_aside(dup, log)
+            .and_then(|_| fs::rename(&tmp_path, dup))
+            .and_then(|_| r.created(dup, size)),
+        None => fs::rename(&tmp_path, dup),
+    };
+    if swapped.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    swapped
+}
+/**************************** rust_dupes ends *****************************/
+
+/**************************** rust_hash starts *****************************/
+
+pub struct HashConfig<'a> {
+    pub dirs: Vec<&'a str>,
+    pub patterns: Vec<&'a str>,
+    pub algorithm: Option<&'a str>,
+    pub output: Option<&'a str>,
+    pub check: Option<&'a str>,
+    pub log: Logger,
+}
+
+pub fn run_hash(config: &HashConfig) -> Result<(), &'static str> {
+    let algorithm: HashAlgorithm = config.parse_algorithm()?;
+    if let Some(manifest) = config.check {
+        return run_hash_check(manifest, algorithm, &config.log);
+    }
+
+    let v_dirs: Vec<PathBuf> = config.parse_dirs()?;
+    let v_pats: Vec<Regex> = config.parse_patterns()?;
+    let mut output: Option<File> = config.parse_output()?;
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    for d in &v_dirs {
+        let mut found: Vec<MyFile> = Vec::new();
+        get_matched_files(&mut found, d, &v_pats, None, false, &config.log);
+        found.sort_by(|a, b| a.path.cmp(&b.path));
+        files.extend(found.into_iter().map(|f| PathBuf::from(f.path)));
+    }
+    // the manifest may be among the files when it is written into a scanned directory
+    if let Some(out) = config.output {
+        let out = std::path::absolute(out).unwrap_or_else(|_| PathBuf::from(out));
+        files.retain(|f| std::path::absolute(f).map_or(true, |f| f != out));
+    }
+
+    let hashes: Vec<io::Result<String>> =
+        files.par_iter().map(|f| hash_file(f, algorithm)).collect();
+    for (f, h) in files.iter().zip(hashes) {
+        match h {
+            Ok(h) => {
+                let line = manifest_line(&h, f);
+                match &mut output {
+                    Some(out) => {
+                        writeln!(out, "{}", line).map_err(|_| "Could not write the manifest")?
+                    }
+                    None => println!("{}", line),
+                }
+            }
+            Err(err) => {
+                error!(config.log, "Could not read file"; "path" => %f.display(), "error" => %err)
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn run_hash_check(
+    manifest: &str,
+    algorithm: HashAlgorithm,
+    log: &Logger,
+) -> Result<(), &'static str> {
+    let text = fs::read_to_string(manifest).map_err(|_| "Could not read the manifest")?;
+    let results = check_manifest(&text, algorithm, log);
+    let mut bad = 0;
+    for (path, status) in &results {
+        println!("{}: {}", path.display(), status);
+        if *status != CheckStatus::Ok {
+            bad += 1;
+        }
+    }
+    if results.is_empty() {
+        return Err("No checksums found in the manifest");
+    }
+    if bad > 0 {
+        error!(log, "Files did not match the manifest"; "failed" => bad, "total" => results.len());
+        return Err("Verification failed");
+    }
+    Ok(())
+}
+
+impl<'a> HashConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
+        let dirs: Vec<&'a str> = match args.values_of("dirs") {
+            Some(d) => d.collect(),
+            None => Vec::new(),
+        };
+        let patterns: Vec<&'a str> = match args.values_of("patterns") {
+            Some(p) => p.collect(),
+            None => Vec::new(),
+        };
+        let algorithm: Option<&'a str> = args.value_of("algorithm");
+        let output: Option<&'a str> = args.value_of("output");
+        let check: Option<&'a str> = args.value_of("check");
+
+        HashConfig {
+            dirs,
+            patterns,
+            algorithm,
+            output,
+            check,
+            log: log.new(o!("command" => "hash")),
+        }
+    }
+
+    pub fn parse_dirs(&self) -> Result<Vec<PathBuf>, &'static str> {
+        let mut res: Vec<PathBuf> = Vec::new();
+        let mut parsed = false;
+        for d in &self.dirs {
+            let dir = PathBuf::from(d);
+            if dir.is_dir() {
+                parsed = true;
+                res.push(dir);
+            } else {
+                warn!(self.log, "Invalid or inaccessible directory, ignoring"; "dir" => d);
+            }
+        }
+        if parsed {
+            Ok(res)
+        } else {
+            Err("No valid directories given")
+        }
+    }
+
+    /// The find-style patterns, or one matching every file if none were given.
+    pub fn parse_patterns(&self) -> Result<Vec<Regex>, &'static str> {
+        if self.patterns.is_empty() {
+            parse_regexes(&[""], &self.log)
+        } else {
+            parse_regexes(&self.patterns, &self.log)
+        }
+    }
+
+    pub fn parse_algorithm(&self) -> Result<HashAlgorithm, &'static str> {
+        match self.algorithm {
+            Some(a) => HashAlgorithm::from_name(a)
+                .ok_or("Invalid --algorithm, expected sha256, blake3 or crc32"),
+            None => Ok(HashAlgorithm::Sha256),
+        }
+    }
+
+    pub fn parse_output(&self) -> Result<Option<File>, &'static str> {
+        match self.output {
+            Some(o) => File::create(o)
+                .map(Some)
+                .map_err(|_| "Could not create the manifest file"),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Blake3,
+    Crc32,
+}
+
+impl HashAlgorithm {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('-', "").as_str() {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "blake3" | "b3" => Some(HashAlgorithm::Blake3),
+            "crc32" => Some(HashAlgorithm::Crc32),
+            _ => None,
+        }
+    }
+}
+
+/// Digest of the contents of `path` in lower case hex.
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0u8; 64 * 1024];
+    match algorithm {
+        HashAlgorithm::Sha256 => {
+            use sha2::Digest;
+            let mut hasher = sha2::Sha256::new();
+            io::copy(&mut file, &mut hasher)?;
+            Ok(hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect())
+        }
+        HashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            hasher.update_reader(file)?;
+            Ok(hasher.finalize().to_hex().to_string())
+        }
+        HashAlgorithm::Crc32 => {
+            let mut hasher = crc32fast::Hasher::new();
+            loop {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+            }
+            Ok(format!("{:08x}", hasher.finalize()))
+        }
+    }
+}
+
+/// A manifest line as `sha256sum` writes it: the digest, two spaces and the
+/// path. Paths with a backslash or newline are escaped and the line starts
+/// with a backslash.
+pub fn manifest_line(hash: &str, path: &Path) -> String {
+    let name = path.to_string_lossy();
+    if name.contains('\\') || name.contains('\n') {
+        format!(
+            "\\{}  {}",
+            hash,
+            name.replace('\\', "\\\\").replace('\n', "\\n")
+        )
+    } else {
+        format!("{}  {}", hash, name)
+    }
+}
+
+/// Split a manifest line into the digest and the path. Both the text (`  `)
+/// and binary (` *`) markers of `sha256sum` are accepted.
+pub fn parse_manifest_line(line: &str) -> Option<(String, PathBuf)> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (hash, rest) = line.split_once(' ')?;
+    let name = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+    if hash.is_empty() || !hash.chars().all(|c| c.is_ascii_hexdigit()) || name.is_empty() {
+        return None;
+    }
+    let name = if escaped {
+        let mut res = String::with_capacity(name.len());
+        let mut chars = name.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                res.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => res.push('\n'),
+                Some(other) => res.push(other),
+                None => res.push('\\'),
+            }
+        }
+        res
+    } else {
+        name.to_string()
+    };
+    Some((hash.to_ascii_lowercase(), PathBuf::from(name)))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+    Missing,
+}
+
+impl std::fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            CheckStatus::Ok => "OK",
+            CheckStatus::Failed => "FAILED",
+            CheckStatus::Missing => "MISSING",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Hash every file listed in `manifest` and compare. Lines that aren't
+/// checksums are reported and skipped.
+pub fn check_manifest(
+    manifest: &str,
+    algorithm: HashAlgorithm,
+    log: &Logger,
+) -> Vec<(PathBuf, CheckStatus)> {
+    let mut entries: Vec<(String, PathBuf)> = Vec::new();
+    for (i, line) in (1..).zip(manifest.lines()) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_manifest_line(line) {
+            Some(entry) => entries.push(entry),
+            None => warn!(log, "Not a checksum line, skipping"; "line" => i),
+        }
+    }
+    entries
+        .par_iter()
+        .map(|(expected, path)| {
+            let status = match hash_file(path, algorithm) {
+                Ok(h) if h == *expected => CheckStatus::Ok,
+                Ok(_) => CheckStatus::Failed,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => CheckStatus::Missing,
+                Err(_) => CheckStatus::Failed,
+            };
+            (path.clone(), status)
+        })
+        .collect()
+}
+/**************************** rust_hash ends *****************************/
+
+/**************************** rust_snapshot starts *****************************/
+
+pub struct SnapshotConfig<'a> {
+    pub action: &'a str,
+    pub dir: Option<&'a str>,
+    pub snapshot: Option<&'a str>,
+    pub output: Option<&'a str>,
+    pub color: Option<&'a str>,
+    pub log: Logger,
+}
+
+pub fn run_snapshot(config: &SnapshotConfig) -> Result<(), &'static str> {
+    let dir: PathBuf = config.parse_dir()?;
+
+    match config.action {
+        "save" => {
+            let snap = take_snapshot(&dir, &config.log);
+            let json = serde_json::to_string_pretty(&snap)
+                .map_err(|_| "Could not serialize the snapshot")?;
+            match config.output {
+                Some(o) => fs::write(o, json).map_err(|_| "Could not write the snapshot file")?,
+                None => println!("{}", json),
+            }
+        }
+        "diff" => {
+            let path = config.snapshot.ok_or("No snapshot file given")?;
+            let text = fs::read_to_string(path).map_err(|_| "Could not read the snapshot file")?;
+            let old: Snapshot = serde_json::from_str(&text).map_err(|_| "Invalid snapshot file")?;
+            let diff = diff_snapshots(&old, &take_snapshot(&dir, &config.log));
+            let colors: Colors = parse_color(config.color)?;
+            for line in diff.lines_colored(&colors) {
+                println!("{}", line);
+            }
+            println!(
+                "{} added, {} removed, {} modified, {} renamed",
+                diff.added.len(),
+                diff.removed.len(),
+                diff.modified.len(),
+                diff.renamed.len()
+            );
+        }
+        _ => return Err("Unknown snapshot action, expected save or diff"),
+    }
+
+    Ok(())
+}
+
+impl<'a> SnapshotConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
+        let (action, sub) = args.subcommand().unwrap_or(("", args));
+        let dir: Option<&'a str> = sub.value_of("dir");
+        let snapshot: Option<&'a str> = sub.value_of("snapshot");
+        let output: Option<&'a str> = sub.value_of("output");
+        let color: Option<&'a str> = sub.value_of("color");
+
+        SnapshotConfig {
+            action,
+            dir,
+            snapshot,
+            output,
+            color,
+            log: log.new(o!("command" => "snapshot")),
+        }
+    }
+
+    pub fn parse_dir(&self) -> Result<PathBuf, &'static str> {
+        let dir = PathBuf::from(self.dir.unwrap_or("."));
+        if dir.is_dir() {
+            Ok(dir)
+        } else {
+            Err("Invalid directory given")
+        }
+    }
+}
+
+/// Digest used for the `hash` of snapshot entries.
+const SNAPSHOT_HASH: HashAlgorithm = HashAlgorithm::Blake3;
+
+/// The state of every file below a directory at one point in time.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Snapshot {
+    pub root: PathBuf,
+    pub created: SystemTime,
+    pub files: Vec<SnapshotEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    /// Relative to the snapshot root.
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+    pub mode: u32,
+    /// BLAKE3 digest of the contents.
+    pub hash: String,
+}
+
+/// Record every file below `dir`, hashing them in parallel. Paths are kept
+/// relative to `dir`, so a snapshot can be compared with a copy elsewhere.
+pub fn take_snapshot(dir: &Path, log: &Logger) -> Snapshot {
+    let mut found: Vec<MyFile> = Vec::new();
+    if let Ok(all) = parse_regexes(&[""], log) {
+        get_matched_files(&mut found, dir, &all, None, false, log);
+    }
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let files: Vec<SnapshotEntry> = found
+        .par_iter()
+        .filter_map(|f| match f.content_hash(SNAPSHOT_HASH) {
+            Ok(hash) => Some(SnapshotEntry {
+                path: Path::new(&f.path)
+                    .strip_prefix(dir)
+                    .map(Path::to_path_buf)
+                    .unwrap_or_else(|_| PathBuf::from(&f.path)),
+                size: f.size_bytes,
+                modified: f.modified,
+                mode: f.mode & 0o7777,
+                hash,
+            }),
+            Err(err) => {
+                warn!(log, "Could not read file, skipping"; "path" => &f.path, "error" => %err);
+                None
+            }
+        })
+        .collect();
+
+    Snapshot {
+        root: std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf()),
+        created: SystemTime::now(),
+        files,
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    /// Same path, different contents or mode.
+    pub modified: Vec<PathBuf>,
+    /// Old and new path of files that moved without changing.
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+}
+
+impl SnapshotDiff {
+    /// One line per change, sorted by path.
+    pub fn lines(&self) -> Vec<String> {
+        self.lines_colored(&Colors::default())
+    }
+
+    /// Like `lines`, with additions in green, removals in red, modifications
+    /// in yellow and renames in cyan.
+    pub fn lines_colored(&self, colors: &Colors) -> Vec<String> {
+        let mut res: Vec<(&Path, String)> = Vec::new();
+        for p in &self.added {
+            res.push((p, colors.paint("32", &format!("added     {}", p.display()))));
+        }
+        for p in &self.removed {
+            res.push((p, colors.paint("31", &format!("removed   {}", p.display()))));
+        }
+        for p in &self.modified {
+            res.push((p, colors.paint("33", &format!("modified  {}", p.display()))));
+        }
+        for (from, to) in &self.renamed {
+            let line = format!("renamed   {} -> {}", from.display(), to.display());
+            res.push((from, colors.paint("36", &line)));
+        }
+        res.sort_by(|a, b| a.0.cmp(b.0));
+        res.into_iter().map(|(_, line)| line).collect()
+    }
+}
+
+/// Compare two snapshots. A file that disappeared and one that appeared with
+/// the same contents count as a rename rather than a removal and an addition.
+pub fn diff_snapshots(old: &Snapshot, new: &Snapshot) -> SnapshotDiff {
+    let old_files: HashMap<&PathBuf, &SnapshotEntry> =
+        old.files.iter().map(|f| (&f.path, f)).collect();
+    let new_files: HashMap<&PathBuf, &SnapshotEntry> =
+        new.files.iter().map(|f| (&f.path, f)).collect();
+    let mut diff = SnapshotDiff::default();
+
+    let mut gone: Vec<&SnapshotEntry> = Vec::new();
+    for f in &old.files {
+        match new_files.get(&f.path) {
+            Some(n) if n.hash != f.hash || n.mode != f.mode => diff.modified.push(f.path.clone()),
+            Some(_) => {}
+            None => gone.push(f),
+        }
+    }
+
+    // match up files that only moved, each old file at most once
+    let mut by_hash: HashMap<&str, Vec<&SnapshotEntry>> = HashMap::new();
+    for f in gone.iter().rev() {
+        by_hash.entry(f.hash.as_str()).or_default().push(f);
+    }
+    for f in &new.files {
+        if old_files.contains_key(&f.path) {
+            continue;
+        }
+        match by_hash.get_mut(f.hash.as_str()).and_then(|v| v.pop()) {
+            Some(from) => diff.renamed.push((from.path.clone(), f.path.clone())),
+            None => diff.added.push(f.path.clone()),
+        }
+    }
+    let renamed: HashSet<&PathBuf> = diff.renamed.iter().map(|(from, _)| from).collect();
+    diff.removed = gone
+        .iter()
+        .filter(|f| !renamed.contains(&f.path))
+        .map(|f| f.path.clone())
+        .collect();
+    diff
+}
+/**************************** rust_snapshot ends *****************************/
+
+/**************************** rust_watch starts *****************************/
+
+pub struct WatchConfig<'a> {
+    pub dirs: Vec<&'a str>,
+    pub patterns: Vec<&'a str>,
+    pub exec: Option<&'a str>,
+    pub replace: Option<&'a str>,
+    pub all: bool,
+    pub debounce: Option<&'a str>,
+    pub color: Option<&'a str>,
+    pub log: Logger,
+}
+
+pub fn run_watch(config: &WatchConfig) -> Result<(), &'static str> {
+    let v_dirs: Vec<PathBuf> = config.parse_dirs()?;
+    let v_pats: Vec<Regex> = config.parse_patterns()?;
+    let debounce: Duration = parse_debounce(config.debounce)?;
+    let colors: Colors = parse_color(config.color)?;
+
+    let mut watcher =
+        Watcher::new(&config.log).map_err(|_| "Could not start watching for changes")?;
+    for dir in &v_dirs {
+        watcher
+            .add_tree(dir)
+            .map_err(|_| "Could not watch the directories")?;
+    }
+    loop {
+        let changes: Vec<Change> = watcher
+            .wait(debounce)
+            .map_err(|_| "Watching for changes failed")?
+            .into_iter()
+            .filter(|c| c.matches(&v_pats))
+            .collect();
+        if changes.is_empty() {
+            continue;
+        }
+        for c in &changes {
+            let code = match c.kind {
+                ChangeKind::Created => "32",
+                ChangeKind::Modified => "33",
+                ChangeKind::Deleted => "31",
+            };
+            let kind = format!("{:<9}", c.kind.to_string());
+            println!("{} {}", colors.paint(code, &kind), c.path.display());
+        }
+        if let Some(exec) = config.exec {
+            let paths: Vec<String> = changes
+                .iter()
+                .filter(|c| c.kind != ChangeKind::Deleted)
+                .map(|c| c.path.to_string_lossy().into_owned())
+                .collect();
+            // nothing to pass when all the files are gone
+            if config.replace.is_none() || !paths.is_empty() {
+                run_exec(exec, config.replace, &paths, config.all, &config.log);
+                // what the command wrote itself, or it would run again and again
+                watcher.drain().map_err(|_| "Watching for changes failed")?;
+            }
+        }
+    }
+}
+
+impl<'a> WatchConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
+        let dirs: Vec<&'a str> = args.values_of("dirs").unwrap().collect();
+        let patterns: Vec<&'a str> = match args.values_of("patterns") {
+            Some(p) => p.collect(),
+            None => Vec::new(),
+        };
+        let exec: Option<&'a str> = args.value_of("exec");
+        let replace: Option<&'a str> = args.value_of("replace");
+        let all: bool = args.is_present("all");
+        let debounce: Option<&'a str> = args.value_of("debounce");
+        let color: Option<&'a str> = args.value_of("color");
+
+        WatchConfig {
+            dirs,
+            patterns,
+            exec,
+            replace,
+            all,
+            debounce,
+            color,
+            log: log.new(o!("command" => "watch")),
+        }
+    }
+
+    pub fn parse_dirs(&self) -> Result<Vec<PathBuf>, &'static str> {
+        let mut res: Vec<PathBuf> = Vec::new();
+        let mut parsed = false;
+        for d in &self.dirs {
+            let dir = PathBuf::from(d);
+            if dir.is_dir() {
+                parsed = true;
+                res.push(dir);
+            } else {
+                warn!(self.log, "Invalid or inaccessible directory, ignoring"; "dir" => d);
+            }
+        }
+        if parsed {
+            Ok(res)
+        } else {
+            Err("No valid directories given")
+        }
+    }
+
+    /// The find-style patterns, or one matching every file if none were given.
+    pub fn parse_patterns(&self) -> Result<Vec<Regex>, &'static str> {
+        if self.patterns.is_empty() {
+            parse_regexes(&[""], &self.log)
+        } else {
+            parse_regexes(&self.patterns, &self.log)
+        }
+    }
+}
+
+/// `--debounce` in milliseconds, 200 by default.
+fn parse_debounce(debounce: Option<&str>) -> Result<Duration, &'static str> {
+    match debounce {
+        Some(ms) => ms
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| "Invalid --debounce, expected milliseconds"),
+        None => Ok(Duration::from_millis(200)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+impl std::fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ChangeKind::Created => "created",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Deleted => "deleted",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub path: PathBuf,
+}
+
+impl Change {
+    /// Whether the path matches one of the find-style patterns.
+    pub fn matches(&self, pats: &[Regex]) -> bool {
+        let path = self.path.to_string_lossy();
+        pats.iter().any(|rgx| rgx.is_match(&path))
+    }
+}
+
+/// Watches directory trees for changed files with inotify.
+pub struct Watcher {
+    fd: OwnedFd,
+    dirs: HashMap<i32, PathBuf>,
+    /// Canonical paths of files whose changes are never reported.
+    ignored: Vec<PathBuf>,
+    log: Logger,
+}
+
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_MODIFY
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_ONLYDIR;
+
+impl Watcher {
+    pub fn new(log: &Logger) -> io::Result<Self> {
+        // SAFETY: no pointers involved, the descriptor is owned from here on
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Watcher {
+            // SAFETY: fd was just opened and nothing else owns it
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            dirs: HashMap::new(),
+            ignored: Vec::new(),
+            log: log.clone(),
+        })
+    }
+
+    /// Never report changes to `path`, such as the file results are written to.
+    pub fn ignore(&mut self, path: &Path) {
+        if let Ok(p) = fs::canonicalize(path) {
+            self.ignored.push(p);
+        }
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        self.ignored.iter().any(|i| {
+            i.file_name() == path.file_name()
+                && path
+                    .parent()
+                    .and_then(|p| fs::canonicalize(p).ok())
+                    .as_deref()
+                    == i.parent()
+        })
+    }
+
+    /// Watch `dir` and every directory below it. Returns the files already
+    /// in the directories, which matters for directories that were just created.
+    pub fn add_tree(&mut self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let c_dir = CString::new(dir.as_os_str().as_bytes())?;
+        // SAFETY: c_dir is a valid NUL terminated string
+        let wd =
+            unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), c_dir.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.dirs.insert(wd, dir.to_path_buf());
+
+        let mut files: Vec<PathBuf> = Vec::new();
+        for entry in fs::read_dir(dir)?.flatten() {
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => match self.add_tree(&entry.path()) {
+                    Ok(inner) => files.extend(inner),
+                    Err(err) => {
+                        warn!(
+                            self.log,
+                            "Cannot watch directory";
+                            "dir" => %entry.path().display(),
+                            "error" => %err
+                        )
+                    }
+                },
+                Ok(_) => files.push(entry.path()),
+                Err(_) => {}
+            }
+        }
+        Ok(files)
+    }
+
+    /// Block until something changes, then keep collecting changes until
+    /// nothing has happened for `debounce`. A file touched several times is
+    /// reported once.
+    pub fn wait(&mut self, debounce: Duration) -> io::Result<Vec<Change>> {
+        self.wait_until(debounce, None)
+    }
+
+    /// Like `wait`, but gives up with no changes if nothing happens within `limit`.
+    pub fn wait_timeout(&mut self, debounce: Duration, limit: Duration) -> io::Result<Vec<Change>> {
+        self.wait_until(debounce, Some(Instant::now() + limit))
+    }
+
+    fn wait_until(
+        &mut self,
+        debounce: Duration,
+        deadline: Option<Instant>,
+    ) -> io::Result<Vec<Change>> {
+        let mut changes: Vec<Change> = Vec::new();
+        let mut timeout: i32 = -1;
+        loop {
+            if changes.is_empty() {
+                timeout = match deadline {
+                    Some(d) => poll_millis(d.saturating_duration_since(Instant::now())),
+                    None => -1,
+                };
+            }
+            let mut pollfd = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: pollfd lives for the duration of the call
+            let ready = unsafe { libc::poll(&mut pollfd, 1, timeout) };
+            if ready < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if ready == 0 {
+                // only events we don't report so far, keep waiting
+                if changes.is_empty() && deadline.is_none_or(|d| Instant::now() < d) {
+                    continue;
+                }
+                return Ok(changes);
+            }
+            for change in self.read_events()? {
+                merge_change(&mut changes, change);
+            }
+            timeout = poll_millis(debounce);
+        }
+    }
+
+    /// Throw away the changes seen so far without waiting, such as the ones a
+    /// command run on the last batch made itself. New directories are still watched.
+    pub fn drain(&mut self) -> io::Result<()> {
+        loop {
+            let mut pollfd = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: pollfd lives for the duration of the call
+            let ready = unsafe { libc::poll(&mut pollfd, 1, 0) };
+            if ready < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if ready == 0 {
+                return Ok(());
+            }
+            self.read_events()?;
+        }
+    }
+
+    fn read_events(&mut self) -> io::Result<Vec<Change>> {
+        let mut buf = vec![0u8; 64 * 1024];
+        // SAFETY: buf is valid for writes of its length
+        let n = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut res: Vec<Change> = Vec::new();
+        let mut offset = 0;
+        while offset + header <= n as usize {
+            // SAFETY: the kernel wrote a whole event header at offset
+            let event: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr().cast()) };
+            let name_bytes = &buf[offset + header..offset + header + event.len as usize];
+            offset += header + event.len as usize;
+
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                warn!(self.log, "Too many changes at once, some were missed");
+            }
+            if event.mask & libc::IN_IGNORED != 0 {
+                self.dirs.remove(&event.wd);
+                continue;
+            }
+            let dir = match self.dirs.get(&event.wd) {
+                Some(d) => d.clone(),
+                None => continue,
+            };
+            // the name is padded with NULs
+            let end = name_bytes
+                .iter()
+                .position(|b| *b == 0)
+                .unwrap_or(name_bytes.len());
+            if end == 0 {
+                continue;
+            }
+            let path = dir.join(OsStr::from_bytes(&name_bytes[..end]));
+
+            if event.mask & libc::IN_ISDIR != 0 {
+                // new directories are watched too, and their contents count as created
+                if event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                    if let Ok(files) = self.add_tree(&path) {
+                        res.extend(files.into_iter().map(|path| Change {
+                            kind: ChangeKind::Created,
+                            path,
+                        }));
+                    }
+                }
+                continue;
+            }
+            if !self.ignored.is_empty() && self.is_ignored(&path) {
+                continue;
+            }
+            let kind = if event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                ChangeKind::Created
+            } else if event.mask & (libc::IN_DELETE | libc::IN_MOVED_FROM) != 0 {
+                ChangeKind::Deleted
+            } else {
+                ChangeKind::Modified
+            };
+            res.push(Change { kind, path });
+        }
+        Ok(res)
+    }
+}
+
+fn poll_millis(d: Duration) -> i32 {
+    d.as_millis().min(i32::MAX as u128) as i32
+}
+
+/// Fold `change` into `changes`, keeping one entry per path.
+fn merge_change(changes: &mut Vec<Change>, change: Change) {
+    let existing = changes.iter().position(|c| c.path == change.path);
+    let i = match existing {
+        Some(i) => i,
+        None => {
+            changes.push(change);
+            return;
+        }
+    };
+    match (changes[i].kind, change.kind) {
+        // a file that came and went again in one batch never existed for us
+        (ChangeKind::Created, ChangeKind::Deleted) => {
+            changes.remove(i);
+        }
+        (ChangeKind::Created, _) => {}
+        // deleted and written again
+        (ChangeKind::Deleted, ChangeKind::Created) => changes[i].kind = ChangeKind::Modified,
+        (_, kind) => changes[i].kind = kind,
+    }
+}
+/**************************** rust_watch ends *****************************/
+
+/**************************** rust_archive starts *****************************/
+
+pub struct ArchiveConfig<'a> {
+    pub action: &'a str,
+    pub archive: Option<&'a str>,
+    pub dirs: Vec<&'a str>,
+    pub patterns: Vec<&'a str>,
+    pub dest: Option<&'a str>,
+    pub color: Option<&'a str>,
+    pub log: Logger,
+}
+
+pub fn run_archive(config: &ArchiveConfig) -> Result<(), &'static str> {
+    let archive = Path::new(config.archive.ok_or("No archive given")?);
+    let format = ArchiveFormat::from_path(archive)
+        .ok_or("Unknown archive format, expected .tar, .tar.gz, .tgz or .zip")?;
+
+    match config.action {
+        "create" => {
+            let v_dirs: Vec<PathBuf> = config.parse_dirs()?;
+            let v_pats: Vec<Regex> = config.parse_patterns()?;
+            let mut members: Vec<(PathBuf, PathBuf)> = Vec::new();
+            for dir in &v_dirs {
+                let mut found: Vec<MyFile> = Vec::new();
+                get_matched_files(&mut found, dir, &v_pats, None, false, &config.log);
+                found.sort_by(|a, b| a.path.cmp(&b.path));
+                for f in found {
+                    let path = PathBuf::from(&f.path);
+                    let name = archive_name(dir, &path);
+                    members.push((path, name));
+                }
+            }
+            let count = create_archive(archive, format, &members)
+                .map_err(|_| "Could not write the archive")?;
+            info!(config.log, "Created archive"; "archive" => %archive.display(), "files" => count);
+            println!("{} files archived in {}", count, archive.display());
+        }
+        "extract" => {
+            let dest = PathBuf::from(config.dest.unwrap_or("."));
+            fs::create_dir_all(&dest).map_err(|_| "Could not create the destination")?;
+            let (count, skipped) = extract_archive(archive, format, &dest, &config.log)
+                .map_err(|_| "Could not extract the archive")?;
+            info!(
+                config.log,
+                "Extracted archive";
+                "archive" => %archive.display(),
+                "entries" => count,
+                "skipped" => skipped
+            );
+            println!("{} entries extracted to {}", count, dest.display());
+            if skipped > 0 {
+                return Err("Some members of the archive were skipped");
+            }
+        }
+        "list" => {
+            let files = list_archive(archive, format).map_err(|_| "Could not read the archive")?;
+            let colors: Colors = parse_color(config.color)?;
+            if let Some(sv) = display(&files, &mut None, &colors) {
+                for s in sv {
+                    println!("{}", s);
+                }
+            }
+        }
+        _ => return Err("Unknown archive action, expected create, extract or list"),
+    }
+
+    Ok(())
+}
+
+impl<'a> ArchiveConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches, log: &Logger) -> Self {
+        let (action, sub) = args.subcommand().unwrap_or(("", args));
+        // create names the archive with -o, extract and list take it as a positional
+        let archive: Option<&'a str> = sub.value_of("output").or(sub.value_of("archive"));
+        let dirs: Vec<&'a str> = match sub.values_of("dirs") {
+            Some(d) => d.collect(),
+            None => Vec::new(),
+        };
+        let patterns: Vec<&'a str> = match sub.values_of("patterns") {
+            Some(p) => p.collect(),
+            None => Vec::new(),
+        };
+        let dest: Option<&'a str> = sub.value_of("dest");
+        let color: Option<&'a str> = sub.value_of("color");
+
+        ArchiveConfig {
+            action,
+            archive,
+            dirs,
+            patterns,
+            dest,
+            color,
+            log: log.new(o!("command" => "archive")),
+        }
+    }
+
+    pub fn parse_dirs(&self) -> Result<Vec<PathBuf>, &'static str> {
+        let mut res: Vec<PathBuf> = Vec::new();
+        let mut parsed = false;
+        for d in &self.dirs {
+            let dir = PathBuf::from(d);
+            if dir.is_dir() {
+                parsed = true;
+                res.push(dir);
+            } else {
+                warn!(self.log, "Invalid or inaccessible directory, ignoring"; "dir" => d);
+            }
+        }
+        if parsed {
+            Ok(res)
+        } else {
+            Err("No valid directories given")
+        }
+    }
+
+    /// The find-style patterns, or one matching every file if none were given.
+    pub fn parse_patterns(&self) -> Result<Vec<Regex>, &'static str> {
+        if self.patterns.is_empty() {
+            parse_regexes(&[""], &self.log)
+        } else {
+            parse_regexes(&self.patterns, &self.log)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Pick the format from the file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Name of `file` inside the archive: its path below `dir`, prefixed with the
+/// name of `dir` itself so that `--dirs src` extracts back into `src/`.
+fn archive_name(dir: &Path, file: &Path) -> PathBuf {
+    let rel = file.strip_prefix(dir).unwrap_or(file);
+    match dir.file_name() {
+        Some(base) => Path::new(base).join(rel),
+        None => rel.to_path_buf(),
+    }
+}
+
+/// Write `members`, pairs of (file on disk, name in the archive), to `archive`.
+/// Mode and mtime are stored with each file. The archive itself is skipped if
+/// it lies in one of the archived directories. Returns the number of files written.
+pub fn create_archive(
+    archive: &Path,
+    format: ArchiveFormat,
+    members: &[(PathBuf, PathBuf)],
+) -> io::Result<usize> {
+    let out = File::create(archive)?;
+    let own = out.metadata()?;
+    let members: Vec<&(PathBuf, PathBuf)> = members
+        .iter()
+        .filter(|(src, _)| match fs::metadata(src) {
+            Ok(m) => (m.dev(), m.ino()) != (own.dev(), own.ino()),
+            Err(_) => false,
+        })
+        .collect();
+
+    match format {
+        ArchiveFormat::Tar => {
+            write_tar(io::BufWriter::new(out), &members)?.flush()?;
+        }
+        ArchiveFormat::TarGz => {
+            let gz = flate2::write::GzEncoder::new(out, flate2::Compression::default());
+            write_tar(gz, &members)?.finish()?;
+        }
+        ArchiveFormat::Zip => write_zip(out, &members)?,
+    }
+    Ok(members.len())
+}
+
+fn write_tar<W: Write>(out: W, members: &[&(PathBuf, PathBuf)]) -> io::Result<W> {
+    let mut builder = tar::Builder::new(out);
+    for (src, name) in members {
+        builder.append_path_with_name(src, name)?;
+    }
+    builder.into_inner()
+}
+
+fn write_zip(out: File, members: &[&(PathBuf, PathBuf)]) -> io::Result<()> {
+    let mut zip = zip::ZipWriter::new(io::BufWriter::new(out));
+    for (src, name) in members {
+        let meta = fs::metadata(src)?;
+        let mut options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .unix_permissions(meta.mode() & 0o7777)
+            .large_file(meta.len() >= u32::MAX as u64);
+        if let Some(time) = meta.modified().ok().and_then(to_zip_time) {
+            options = options.last_modified_time(time);
+        }
+        let name = name
+            .to_str()
+            .ok_or_else(|| io::Error::other("file name is not valid UTF-8"))?;
+        zip.start_file(name, options).map_err(io::Error::other)?;
+        io::copy(&mut File::open(src)?, &mut zip)?;
+    }
+    zip.finish().map_err(io::Error::other)?.flush()
+}
+
+/// Zip stores local time with a two second resolution and nothing before 1980.
+fn to_zip_time(time: SystemTime) -> Option<zip::DateTime> {
+    let t: DateTime<Local> = time.into();
+    zip::DateTime::from_date_and_time(
+        u16::try_from(t.year()).ok()?,
+        t.month() as u8,
+        t.day() as u8,
+        t.hour() as u8,
+        t.minute() as u8,
+        t.second() as u8,
+    )
+    .ok()
+}
+
+fn from_zip_time(time: zip::DateTime) -> Option<SystemTime> {
+    Local
+        .with_ymd_and_hms(
+            time.year() as i32,
+            time.month() as u32,
+            time.day() as u32,
+            time.hour() as u32,
+            time.minute() as u32,
+            time.second() as u32,
+        )
+        .single()
+        .map(SystemTime::from)
+}
+
+/// What an archive member turns into on disk.
+enum EntryKind {
+    File,
+    Dir,
+    Symlink(PathBuf),
+    /// Target named as another member of the archive.
+    HardLink(PathBuf),
+    Other,
+}
+
+/// Unpack `archive` below `dest`. Members with absolute names or `..`, members
+/// that would be written through a symlink, symlinks pointing outside `dest`
+/// and members blocked by a non-empty directory are skipped with a message.
+/// Returns the number of entries extracted and skipped.
+pub fn extract_archive(
+    archive: &Path,
+    format: ArchiveFormat,
+    dest: &Path,
+    log: &Logger,
+) -> io::Result<(usize, usize)> {
+    let file = File::open(archive)?;
+    let mut dirs: Vec<(PathBuf, Option<u32>, Option<SystemTime>)> = Vec::new();
+    let counts = match format {
+        ArchiveFormat::Tar => extract_tar(io::BufReader::new(file), dest, &mut dirs, log)?,
+        ArchiveFormat::TarGz => {
+            let gz = flate2::read::GzDecoder::new(io::BufReader::new(file));
+            extract_tar(gz, dest, &mut dirs, log)?
+        }
+        ArchiveFormat::Zip => extract_zip(file, dest, &mut dirs, log)?,
+    };
+
+    // directories last, deepest first, so that writing their contents neither
+    // trips over a read-only mode nor bumps the restored mtime
+    dirs.sort_by(|a, b| b.0.cmp(&a.0));
+    for (dir, mode, mtime) in dirs {
+        let mode = mode.unwrap_or(0o755) & 0o777;
+        if let Some(t) = mtime {
+            if let Ok(f) = File::open(&dir) {
+                let _ = f.set_times(FileTimes::new().set_accessed(t).set_modified(t));
+            }
+        }
+        fs::set_permissions(&dir, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(counts)
+}
+
+fn extract_tar<R: Read>(
+    reader: R,
+    dest: &Path,
+    dirs: &mut Vec<(PathBuf, Option<u32>, Option<SystemTime>)>,
+    log: &Logger,
+) -> io::Result<(usize, usize)> {
+    let mut archive = tar::Archive::new(reader);
+    let (mut count, mut skipped) = (0, 0);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.into_owned();
+        let header = entry.header();
+        let mode = header.mode().ok();
+        let mtime = header
+            .mtime()
+            .ok()
+            .map(|s| UNIX_EPOCH + Duration::from_secs(s));
+        let kind = match header.entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => EntryKind::File,
+            tar::EntryType::Directory => EntryKind::Dir,
+            tar::EntryType::Symlink | tar::EntryType::Link => {
+                let target = match entry.link_name()? {
+                    Some(t) => t.into_owned(),
+                    None => PathBuf::new(),
+                };
+                if entry.header().entry_type() == tar::EntryType::Symlink {
+                    EntryKind::Symlink(target)
+                } else {
+                    EntryKind::HardLink(target)
+                }
+            }
+            _ => EntryKind::Other,
+        };
+        if extract_entry(dest, &name, kind, mode, mtime, &mut entry, dirs, log)? {
+            count += 1;
+        } else {
+            skipped += 1;
+        }
+    }
+    Ok((count, skipped))
+}
+
+fn extract_zip(
+    file: File,
+    dest: &Path,
+    dirs: &mut Vec<(PathBuf, Option<u32>, Option<SystemTime>)>,
+    log: &Logger,
+) -> io::Result<(usize, usize)> {
+    let mut zip = zip::ZipArchive::new(io::BufReader::new(file)).map_err(io::Error::other)?;
+    let (mut count, mut skipped) = (0, 0);
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(io::Error::other)?;
+        let name = PathBuf::from(entry.name());
+        let mode = entry.unix_mode();
+        let mtime = entry.last_modified().and_then(from_zip_time);
+        let kind = if entry.is_dir() {
+            EntryKind::Dir
+        } else if entry.is_symlink() {
+            let mut target = Vec::new();
+            entry.read_to_end(&mut target)?;
+            EntryKind::Symlink(PathBuf::from(OsString::from_vec(target)))
+        } else {
+            EntryKind::File
+        };
+        if extract_entry(dest, &name, kind, mode, mtime, &mut entry, dirs, log)? {
+            count += 1;
+        } else {
+            skipped += 1;
+        }
+    }
+    Ok((count, skipped))
+}
+
+/// Write one member below `dest`, returning whether it was extracted.
+#[allow(clippy::too_many_arguments)]
+fn extract_entry(
+    dest: &Path,
+    name: &Path,
+    kind: EntryKind,
+    mode: Option<u32>,
+    mtime: Option<SystemTime>,
+    data: &mut dyn Read,
+    dirs: &mut Vec<(PathBuf, Option<u32>, Option<SystemTime>)>,
+    log: &Logger,
+) -> io::Result<bool> {
+    let path = match safe_entry_path(dest, name) {
+        Some(p) => p,
+        None => {
+            warn!(
+                log,
+                "Skipping member that would be written outside the destination";
+                "member" => %name.display()
+            );
+            return Ok(false);
+        }
+    };
+    if through_symlink(dest, &path) {
+        warn!(
+            log,
+            "Skipping member that would be written through a symlink";
+            "member" => %name.display()
+        );
+        return Ok(false);
+    }
+    match &kind {
+        EntryKind::Symlink(target) if !symlink_stays_inside(name, target) => {
+            warn!(
+                log,
+                "Skipping symlink that points outside the destination";
+                "member" => %name.display(),
+                "target" => %target.display()
+            );
+            return Ok(false);
+        }
+        EntryKind::HardLink(target) => {
+            let linkable = match safe_entry_path(dest, target) {
+                Some(src) => {
+                    src != path
+                        && !through_symlink(dest, &src)
+                        && fs::symlink_metadata(&src)
+                            .map(|m| m.is_file())
+                            .unwrap_or(false)
+                }
+                None => false,
+            };
+            if !linkable {
+                warn!(
+                    log,
+                    "Skipping hard link to a file not extracted from the archive";
+                    "member" => %name.display(),
+                    "target" => %target.display()
+                );
+                return Ok(false);
+            }
+        }
+        EntryKind::Other => {
+            warn!(log, "Skipping member of an unsupported type"; "member" => %name.display());
+            return Ok(false);
+        }
+        _ => {}
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // replace what is in the way instead of following or writing into it,
+    // but never a directory with something in it
+    if let Ok(meta) = fs::symlink_metadata(&path) {
+        let keep = match kind {
+            EntryKind::Dir => meta.is_dir(),
+            EntryKind::File => meta.is_file(),
+            _ => false,
+        };
+        let removed = match (keep, meta.is_dir()) {
+            (true, _) => Ok(()),
+            (false, true) => fs::remove_dir(&path),
+            (false, false) => fs::remove_file(&path),
+        };
+        if let Err(err) = removed {
+            error!(
+                log,
+                "Skipping member, the path is taken and could not be replaced";
+                "member" => %name.display(),
+                "error" => %err
+            );
+            return Ok(false);
+        }
+    }
+
+    match kind {
+        EntryKind::Dir => {
+            fs::create_dir_all(&path)?;
+            dirs.push((path, mode, mtime));
+        }
+        EntryKind::File => {
+            let mut out = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .custom_flags(libc::O_NOFOLLOW)
+                .mode(0o600)
+                .open(&path)?;
+            io::copy(data, &mut out)?;
+            // setuid, setgid and sticky bits from an archive are not trusted
+            out.set_permissions(fs::Permissions::from_mode(mode.unwrap_or(0o644) & 0o777))?;
+            if let Some(t) = mtime {
+                out.set_times(FileTimes::new().set_accessed(t).set_modified(t))?;
+            }
+        }
+        EntryKind::Symlink(target) => std::os::unix::fs::symlink(target, &path)?,
+        EntryKind::HardLink(target) => {
+            // checked above
+            if let Some(src) = safe_entry_path(dest, &target) {
+                fs::hard_link(src, &path)?;
+            }
+        }
+        EntryKind::Other => {}
+    }
+    Ok(true)
+}
+
+/// Where the member `name` goes below `dest`, or None if the name is absolute,
+/// empty or climbs out with `..`.
+pub fn safe_entry_path(dest: &Path, name: &Path) -> Option<PathBuf> {
+    let mut res = dest.to_path_buf();
+    let mut depth = 0;
+    for comp in name.components() {
+        match comp {
+            Component::Normal(c) => {
+                res.push(c);
+                depth += 1;
+            }
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if depth > 0 {
+        Some(res)
+    } else {
+        None
+    }
+}
+
+/// Whether any directory between `dest` and `path` is a symlink, which an
+/// earlier member could have planted to redirect the writes of later ones.
+fn through_symlink(dest: &Path, path: &Path) -> bool {
+    let rel = match path.parent().and_then(|p| p.strip_prefix(dest).ok()) {
+        Some(r) => r,
+        None => return false,
+    };
+    let mut cur = dest.to_path_buf();
+    for comp in rel.components() {
+        cur.push(comp);
+        if let Ok(meta) = fs::symlink_metadata(&cur) {
+            if meta.file_type().is_symlink() {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// A symlink at member `name` may point to `target` if the target is relative,
+/// only climbs with leading `..` and never above the extraction root. Any
+/// symlink it passes through was held to the same rule when it was extracted.
+pub fn symlink_stays_inside(name: &Path, target: &Path) -> bool {
+    let mut depth = name
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .count() as i64
+        - 1;
+    let mut descended = false;
+    for comp in target.components() {
+        match comp {
+            Component::ParentDir if !descended => {
+                depth -= 1;
+                if depth < 0 {
+                    return false;
+                }
+            }
+            Component::Normal(_) => descended = true,
+            Component::CurDir => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// The members of `archive` as MyFile entries named by their path inside it,
+/// so they can go through `display` like files found on disk.
+pub fn list_archive(archive: &Path, format: ArchiveFormat) -> io::Result<Vec<MyFile>> {
+    let file = File::open(archive)?;
+    match format {
+        ArchiveFormat::Tar => list_tar(io::BufReader::new(file)),
+        ArchiveFormat::TarGz => list_tar(flate2::read::GzDecoder::new(io::BufReader::new(file))),
+        ArchiveFormat::Zip => {
+            let mut zip =
+                zip::ZipArchive::new(io::BufReader::new(file)).map_err(io::Error::other)?;
+            let mut res = Vec::with_capacity(zip.len());
+            for i in 0..zip.len() {
+                let entry = zip.by_index(i).map_err(io::Error::other)?;
+                let kind = if entry.is_dir() {
+                    libc::S_IFDIR
+                } else if entry.is_symlink() {
+                    libc::S_IFLNK
+                } else {
+                    libc::S_IFREG
+                };
+                let perm = entry.unix_mode().unwrap_or(0o644) & 0o7777;
+                let modified = entry
+                    .last_modified()
+                    .and_then(from_zip_time)
+                    .unwrap_or(UNIX_EPOCH);
+                res.push(MyFile::from_archive_entry(
+                    entry.name(),
+                    entry.size(),
+                    kind | perm,
+                    modified,
+                ));
+            }
+            Ok(res)
+        }
+    }
+}
+
+fn list_tar<R: Read>(reader: R) -> io::Result<Vec<MyFile>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut res = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let header = entry.header();
+        let kind = match header.entry_type() {
+            tar::EntryType::Directory => libc::S_IFDIR,
+            tar::EntryType::Symlink => libc::S_IFLNK,
+            _ => libc::S_IFREG,
+        };
+        let perm = header.mode().unwrap_or(0o644) & 0o7777;
+        let modified = UNIX_EPOCH + Duration::from_secs(header.mtime().unwrap_or(0));
+        res.push(MyFile::from_archive_entry(
+            &entry.path()?.to_string_lossy(),
+            header.size().unwrap_or(0),
+            kind | perm,
+            modified,
+        ));
+    }
+    Ok(res)
+}
+
+/// Virtual path of `member` inside `archive`, as find and grep report it.
+pub fn archive_member_path(archive: &Path, member: &str) -> String {
+    format!("{}!/{}", archive.display(), member)
+}
+
+/// Split a virtual path like `logs.tar.gz!/app/x.log` into the archive and the
+/// member name, if the part before `!/` names a supported archive.
+pub fn split_archive_path(path: &str) -> Option<(&Path, &str)> {
+    let (archive, member) = path.split_once("!/")?;
+    let archive = Path::new(archive);
+    ArchiveFormat::from_path(archive)?;
+    Some((archive, member))
+}
+
+/// Stream the regular files in `archive` to `f` along with their names,
+/// without writing anything to disk.
+pub fn for_each_archive_entry(
+    archive: &Path,
+    format: ArchiveFormat,
+    mut f: impl FnMut(&str, &mut dyn Read) -> io::Result<()>,
+) -> io::Result<()> {
+    let file = File::open(archive)?;
+    match format {
+        ArchiveFormat::Tar => for_each_tar_entry(io::BufReader::new(file), f),
+        ArchiveFormat::TarGz => {
+            for_each_tar_entry(flate2::read::GzDecoder::new(io::BufReader::new(file)), f)
+        }
+        ArchiveFormat::Zip => {
+            let mut zip =
+                zip::ZipArchive::new(io::BufReader::new(file)).map_err(io::Error::other)?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i).map_err(io::Error::other)?;
+                if entry.is_file() && !entry.is_symlink() {
+                    let name = entry.name().to_string();
+                    f(&name, &mut entry)?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+fn for_each_tar_entry<R: Read>(
+    reader: R,
+    mut f: impl FnMut(&str, &mut dyn Read) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if matches!(
+            entry.header().entry_type(),
+            tar::EntryType::Regular | tar::EntryType::Continuous
+        ) {
+            let name = entry.path()?.to_string_lossy().into_owned();
+            f(&name, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+/**************************** rust_archive ends *****************************/
+
+/**************************** rust_color starts *****************************/
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+}
+
+/// `--color`, `auto` if it was not given.
+pub fn parse_color(color: Option<&str>) -> Result<Colors, &'static str> {
+    match color {
+        Some(c) => ColorChoice::from_name(c)
+            .map(Colors::new)
+            .ok_or("Invalid --color, expected auto, always or never"),
+        None => Ok(Colors::new(ColorChoice::Auto)),
+    }
+}
+
+/// Colors of grep output, as GNU grep uses them.
+const GREP_FILE: &str = "35";
+const GREP_LINE_NUMBER: &str = "32";
+const GREP_MATCH: &str = "01;31";
+
+/// Used when `LS_COLORS` is not set, the file type part of GNU's defaults.
+const DEFAULT_LS_COLORS: &str =
+    "di=01;34:ln=01;36:pi=40;33:so=01;35:bd=40;33;01:cd=40;33;01:ex=01;32";
+
+/// Whether output gets ANSI colors, and which. The default colors nothing.
+#[derive(Clone, Debug, Default)]
+pub struct Colors {
+    enabled: bool,
+    ls: LsColors,
+}
+
+impl Colors {
+    /// With `Auto`, color only if stdout is a terminal and `NO_COLOR` is unset
+    /// or empty. `Always` colors even with `NO_COLOR`, as asked on the command line.
+    pub fn new(choice: ColorChoice) -> Self {
+        let enabled = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && io::stdout().is_terminal()
+            }
+        };
+        if enabled {
+            Colors::forced(std::env::var("LS_COLORS").ok().as_deref())
+        } else {
+            Colors::default()
+        }
+    }
+
+    /// Colors regardless of the terminal, with file types colored by
+    /// `ls_colors` in the `LS_COLORS` format, or the defaults if None.
+    pub fn forced(ls_colors: Option<&str>) -> Self {
+        Colors {
+            enabled: true,
+            ls: LsColors::parse(ls_colors.unwrap_or(DEFAULT_LS_COLORS)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// `text` wrapped in the SGR sequence `code`, e.g. `01;31`.
+    pub fn paint(&self, code: &str, text: &str) -> String {
+        if self.enabled && !code.is_empty() {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// `text` colored for the type of `f`, see `LsColors::code`.
+    pub fn file(&self, f: &MyFile, text: &str) -> String {
+        self.paint(self.ls_code(f.mode, &f.name), text)
+    }
+
+    /// The `LS_COLORS` code for a file of `mode` called `name`.
+    pub fn ls_code(&self, mode: u32, name: &str) -> &str {
+        self.ls.code(mode, name)
+    }
+
+    pub fn file_name(&self, name: &str) -> String {
+        self.paint(GREP_FILE, name)
+    }
+
+    /// A grep result: the line number, then the line with every match of `rgx` highlighted.
+    pub fn grep_line(&self, line_number: usize, line: &str, rgx: &Regex) -> String {
+        let number = self.paint(GREP_LINE_NUMBER, &line_number.to_string());
+        if !self.enabled {
+            return format!("{}: {}", number, line);
+        }
+        let mut res = String::with_capacity(line.len());
+        let mut last = 0;
+        for m in rgx.find_iter(line).filter(|m| !m.is_empty()) {
+            res.push_str(&line[last..m.start()]);
+            res.push_str(&self.paint(GREP_MATCH, m.as_str()));
+            last = m.end();
+        }
+        res.push_str(&line[last..]);
+        format!("{}: {}", number, res)
+    }
+}
+
+/// File type colors in the `LS_COLORS` format, e.g. `di=01;34:*.rs=00;33`.
+#[derive(Clone, Debug, Default)]
+pub struct LsColors {
+    types: HashMap<String, String>,
+    /// `(suffix, code)` for `*suffix=code`, in the order given.
+    suffixes: Vec<(String, String)>,
+}
+
+impl LsColors {
+    pub fn parse(spec: &str) -> Self {
+        let mut res = LsColors::default();
+        for item in spec.split(':') {
+            if let Some((key, code)) = item.split_once('=') {
+                match key.strip_prefix('*') {
+                    Some(suffix) => res.suffixes.push((suffix.to_string(), code.to_string())),
+                    None => {
+                        res.types.insert(key.to_string(), code.to_string());
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// The code for a file of `mode` called `name`: by type for anything but
+    /// regular files, then by suffix, then `ex` for executables and `fi`.
+    pub fn code(&self, mode: u32, name: &str) -> &str {
+        let key = match mode & libc::S_IFMT {
+            libc::S_IFDIR => "di",
+            libc::S_IFLNK => "ln",
+            libc::S_IFIFO => "pi",
+            libc::S_IFSOCK => "so",
+            libc::S_IFBLK => "bd",
+            libc::S_IFCHR => "cd",
+            _ => {
+                if let Some((_, code)) = self.suffixes.iter().find(|(s, _)| name.ends_with(s)) {
+                    return code;
+                }
+                if mode & 0o111 != 0 {
+                    "ex"
+                } else {
+                    "fi"
+                }
+            }
+        };
+        match self.types.get(key) {
+            // `ln=target` colors links as what they point to, which we do not look up
+            Some(code) if code != "target" => code,
+            _ => "",
+        }
+    }
+}
+
+/**************************** rust_color ends *****************************/
+
+/**************************** rust_log starts *****************************/
+
+/// The global `-v`, `-q`, `--log-file` and `--log-format` options, which
+/// decide what every command logs and where.
+pub struct LogConfig<'a> {
+    pub verbose: u64,
+    pub quiet: bool,
+    pub log_file: Option<&'a str>,
+    pub format: Option<&'a str>,
+}
+
+impl<'a> LogConfig<'a> {
+    pub fn from_args(args: &'a ArgMatches) -> Self {
+        let verbose: u64 = args.occurrences_of("verbose");
+        let quiet: bool = args.is_present("quiet");
+        let log_file: Option<&'a str> = args.value_of("log-file");
+        let format: Option<&'a str> = args.value_of("log-format");
+
+        LogConfig {
+            verbose,
+            quiet,
+            log_file,
+            format,
+        }
+    }
+
+    /// Warnings and errors by default, only errors with `-q`, more with each `-v`.
+    pub fn parse_level(&self) -> Level {
+        if self.quiet {
+            return Level::Error;
+        }
+        match self.verbose {
+            0 => Level::Warning,
+            1 => Level::Info,
+            2 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+
+    /// Log to stderr, or append to `--log-file`, as text or one JSON object per line.
+    pub fn build(&self) -> Result<Logger, &'static str> {
+        let level = self.parse_level();
+        let json = match self.format {
+            None | Some("text") => false,
+            Some("json") => true,
+            Some(_) => return Err("Invalid --log-format, expected text or json"),
+        };
+        let out: Box<dyn Write + Send> = match self.log_file {
+            Some(f) => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(f)
+                    .map_err(|_| "Could not open the log file")?,
+            ),
+            None => Box::new(io::stderr()),
+        };
+
+        // a log that cannot be written should not stop the command
+        let log = if json {
+            let drain = slog_json::Json::new(out).add_default_keys().build();
+            Logger::root(Mutex::new(drain).filter_level(level).ignore_res(), o!())
+        } else {
+            let decorator = slog_term::PlainSyncDecorator::new(out);
+            let format = slog_term::FullFormat::new(decorator);
+            // timestamps only help once the log outlives the terminal
+            let format = if self.log_file.is_some() {
+                format
+            } else {
+                format.use_custom_header_print(print_short_header)
+            };
+            Logger::root(format.build().filter_level(level).ignore_res(), o!())
+        };
+        Ok(log)
+    }
+}
+
+/// `WARN message` without the timestamp, for the terminal.
+fn print_short_header(
+    _: &dyn slog_term::ThreadSafeTimestampFn<Output = io::Result<()>>,
+    rd: &mut dyn slog_term::RecordDecorator,
+    record: &slog::Record,
+    _: bool,
+) -> io::Result<bool> {
+    rd.start_level()?;
+    write!(rd, "{}", record.level().as_short_str())?;
+    rd.start_whitespace()?;
+    write!(rd, " ")?;
+    rd.start_msg()?;
+    write!(rd, "{}", record.msg())?;
+    Ok(true)
+}
+
+/// A logger that drops everything, for configs built outside of main.
+pub fn discard_logger() -> Logger {
+    Logger::root(slog::Discard, o!())
+}
+
+/**************************** rust_log ends *****************************/
+
+/**************************** settings starts *****************************/
+
+/// Defaults read from `$XDG_CONFIG_HOME/rust_file_manager/config`
+/// (`~/.config/rust_file_manager/config`), one `key = value` per line.
+pub struct Settings {
+    /// `remove` moves files to the trash unless `--no-trash` is given.
+    pub trash: bool,
+    /// `remove -I` asks for confirmation when more than this many items are removed.
+    pub interactive_threshold: usize,
+    /// The undo journal keeps at most this many operations.
+    pub journal_max_entries: usize,
+    /// and at most this many bytes of saved files, dropping the oldest first.
+    pub journal_max_size: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            trash: false,
+            interactive_threshold: 3,
+            journal_max_entries: 20,
+            journal_max_size: 1 << 30,
+        }
+    }
+}
+
+impl Settings {
+    /// Missing or unreadable config files just give the defaults.
+    pub fn load(log: &Logger) -> Self {
+        let config = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(d) if !d.is_empty() => PathBuf::from(d),
+            _ => match home_dir() {
+                Ok(h) => h.join(".config"),
+                Err(_) => return Settings::default(),
+            },
+        };
+        match fs::read_to_string(config.join("rust_file_manager/config")) {
+            Ok(contents) => Settings::parse(&contents, log),
+            Err(_) => Settings::default(),
+        }
+    }
+
+    pub fn parse(contents: &str, log: &Logger) -> Self {
+        let mut res = Settings::default();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+                Some(("trash", v)) => res.trash = v == "true",
+                Some(("interactive_threshold", v)) => match v.parse() {
+                    Ok(n) => res.interactive_threshold = n,
+                    Err(_) => warn!(log, "Ignoring invalid interactive_threshold"; "value" => v),
+                },
+                Some(("journal_max_entries", v)) => match v.parse() {
+                    Ok(n) => res.journal_max_entries = n,
+                    Err(_) => warn!(log, "Ignoring invalid journal_max_entries"; "value" => v),
+                },
+                Some(("journal_max_size", v)) => match parse_human_size(v) {
+                    Some(n) => res.journal_max_size = n,
+                    None => warn!(log, "Ignoring invalid journal_max_size"; "value" => v),
+                },
+                _ => warn!(log, "Ignoring unknown config line"; "line" => line),
+            }
+        }
+        res
+    }
+}
+/**************************** settings ends *****************************/
@@ -1,7 +1,10 @@
 use clap::{App, Arg}; // tell Rust you will use these two structs in clap
 use lib::{
-    run_add, run_find, run_grep, run_remove, run_tr, AddConfig, FindConfig, GrepConfig,
-    RemoveConfig, TrConfig,
+    run_add, run_archive, run_copy, run_du, run_dupes, run_find, run_grep, run_hash, run_ls,
+    run_move, run_remove, run_rename, run_snapshot, run_tr, run_trash, run_undo, run_watch,
+    AddConfig, ArchiveConfig, CopyConfig, DuConfig, DupesConfig, FindConfig, GrepConfig,
+    HashConfig, LogConfig, LsConfig, MoveConfig, RemoveConfig, RenameConfig, SnapshotConfig,
+    TrConfig, TrashConfig, UndoConfig, WatchConfig,
 }; // tell Rust you will use these many things from our "lib" module
 
 fn main() {
@@ -10,6 +13,38 @@ fn main() {
         .version("0.1.0")
         .author("Yonas Mekonnen, Hongyang Lin, Spencer Chan")
         .about("Emulate basic Linux commands")
+        .arg(
+            Arg::from("-v, --verbose 'Log more of what is done: -v for each operation, -vv for details'")
+                .required(false)
+                .global(true)
+                .multiple_occurrences(true)
+        )
+        .arg(
+            Arg::from("-q, --quiet 'Only log errors'")
+                .required(false)
+                .global(true)
+                .conflicts_with("verbose")
+        )
+        .arg(
+            Arg::from("--log-file=<file> 'Append the log to this file instead of writing it to stderr'")
+                .required(false)
+                .global(true)
+        )
+        .arg(
+            Arg::from("--log-format=<format> 'Log as text or as one JSON object per line (default: text)'")
+                .required(false)
+                .global(true)
+                .possible_values(&["text", "json"])
+        )
+        .arg(
+            Arg::from("--color=[when] 'Color the output: auto (only on a terminal, and not with NO_COLOR set), always or never'")
+                .required(false)
+                .global(true)
+                .min_values(0)
+                .require_equals(true)
+                .default_missing_value("always")
+                .possible_values(&["auto", "always", "never"])
+        )
         .subcommand(
             App::new("find")
                 .arg(
@@ -38,7 +73,6 @@ fn main() {
                     Arg::from("-x, --exec=<cmd> 'The command to run'")
                     .takes_value(true)
                     .required(false)
-                    .requires("replace")
                     .multiple_values(true)
                 )
                 .arg(
@@ -53,6 +87,24 @@ fn main() {
                     .required(false)
                     .requires_all(&["replace", "exec"])
                 )
+                .arg(
+                    Arg::from("-w, --watch 'Keep running and search again whenever a matching file changes'")
+                    .takes_value(false)
+                    .required(false)
+                )
+                .arg(
+                    Arg::from("--debounce=<ms> 'With --watch, wait until nothing changed for this long (default: 200)'")
+                    .takes_value(true)
+                    .required(false)
+                    .requires("watch")
+                )
+                .arg(
+                    Arg::from("--archives 'Also match the files inside .tar, .tar.gz and .zip archives, reported as archive!/member'")
+                    .takes_value(false)
+                    .required(false)
+                    // archive!/member paths don't exist, no command could open them
+                    .conflicts_with("exec")
+                )
                 // could thread here! just spawn 1 command per found item...
         )
         .subcommand(
@@ -69,13 +121,72 @@ fn main() {
                         .required(true)
                         .multiple_values(true),
                 )
+                .arg(
+                    Arg::from("--no-journal 'Do not record this operation for rust undo'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-a 'Change only the access time'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-m 'Change only the modification time'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-c, --no-create 'Do not create files that do not exist'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-D, --date=<date> 'Use this time instead of now: now, @SECONDS, YYYY-MM-DD [HH:MM[:SS]] or RFC 3339'")
+                        .takes_value(true)
+                        .required(false)
+                        .conflicts_with_all(&["reference", "stamp"])
+                )
+                .arg(
+                    Arg::from("-r, --reference=<file> 'Use the times of this file instead of now'")
+                        .takes_value(true)
+                        .required(false)
+                        .conflicts_with("stamp")
+                )
+                .arg(
+                    Arg::from("-t, --stamp=<stamp> 'Use [[CC]YY]MMDDhhmm[.ss] instead of now'")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-p, --parents 'Create missing parent directories'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--mode=<mode> 'Octal permissions for new files, e.g. 644'")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--template=<file> 'Seed new files with this file, substituting {{name}}, {{dir}} and {{date}}'")
+                        .takes_value(true)
+                        .required(false)
+                        .conflicts_with("content")
+                )
+                .arg(
+                    Arg::from("--content=<text> 'Seed new files with this text, substituting {{name}}, {{dir}} and {{date}}'")
+                        .takes_value(true)
+                        .required(false)
+                )
         )
         .subcommand(
             App::new("remove")
                 .arg(
                     Arg::from("-f, --files=<files> 'Name of the file that's to be removed'")
                         .takes_value(true)
-                        .required(true)
+                        .required(false)
+                        .required_unless_present("pattern")
                         .multiple_values(true),
                 )
                 .arg(
@@ -84,6 +195,68 @@ fn main() {
                         .required(true)
                         .multiple_values(true),
                 )
+                .arg(
+                    Arg::from("--no-journal 'Do not record this operation for rust undo'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--trash 'Move files to the trash instead of deleting them'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--no-trash 'Delete files even if the config enables the trash'")
+                        .takes_value(false)
+                        .required(false)
+                        .conflicts_with("trash")
+                )
+                .arg(
+                    Arg::from("-p, --pattern=<pattern> 'Remove files in the directories whose path matches these regexes'")
+                        .takes_value(true)
+                        .required(false)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::from("-r, --recursive 'Remove directories and their contents, and search subdirectories for --pattern'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--force 'Ignore files that do not exist'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-n, --dry-run 'List what would be removed and its total size without removing anything'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-i, --interactive 'Prompt before every removal'")
+                        .takes_value(false)
+                        .required(false)
+                        .overrides_with("interactive-once")
+                )
+                .arg(
+                    Arg::from("-I, --interactive-once 'Prompt once when removing directories or more than --interactive-threshold items'")
+                        .takes_value(false)
+                        .required(false)
+                        .overrides_with("interactive")
+                )
+                .arg(
+                    Arg::from("--interactive-threshold=<n> 'Item count above which -I prompts (default 3, or interactive_threshold in the config)'")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--shred=[passes] 'Overwrite files with random data (3 passes by default) and zeros before deleting them'")
+                        .required(false)
+                        .min_values(0)
+                        .require_equals(true)
+                        .default_missing_value("3")
+                        .conflicts_with_all(&["trash", "dry-run"])
+                )
         )
         .subcommand(
             // tr++
@@ -115,6 +288,51 @@ fn main() {
                      .takes_value(false)
                      .required(false)
                 )
+                .arg(
+                    Arg::from("--backup=[suffix] 'Keep the original file with this suffix appended (default: ~)'")
+                        .required(false)
+                        .min_values(0)
+                        .require_equals(true)
+                        .default_missing_value("~")
+                )
+                .arg(
+                    Arg::from("--preserve-mtime 'Keep the original access and modification times'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-e, --encoding=<encoding> 'Encoding of the file, detected from its contents by default'")
+                        .takes_value(true)
+                        .required(false)
+                        .possible_values(&["auto", "utf-8", "utf-8-bom", "utf-16le", "utf-16be", "latin1"])
+                )
+                .arg(
+                    Arg::from("--binary-safe 'Replace raw bytes without decoding the file as text'")
+                        .takes_value(false)
+                        .required(false)
+                        .conflicts_with_all(&["encoding", "lines", "after", "between"])
+                )
+                .arg(
+                    Arg::from("--lines=<range> 'Only edit lines in this 1-based range, e.g. 10:50, 10: or :50'")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--after=<regex> 'Only edit lines after the first line matching regex'")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--between=<regex> 'Only edit blocks from a line matching the first regex to a line matching the second'")
+                        .takes_value(true)
+                        .required(false)
+                        .number_of_values(2)
+                )
+                .arg(
+                    Arg::from("--no-journal 'Do not record this operation for rust undo'")
+                        .takes_value(false)
+                        .required(false)
+                )
         )
         .subcommand(
             App::new("grep")
@@ -130,40 +348,569 @@ fn main() {
                         .multiple_values(true)
                         .required(true)
                 )
+                .arg(
+                    Arg::from("-z, --search-zip 'Also search inside .gz, .bz2, .xz and .zst files'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--archives 'Search the files inside .tar, .tar.gz and .zip archives, or one given as archive!/member'")
+                        .takes_value(false)
+                        .required(false)
+                )
+        )
+        .subcommand(
+            App::new("copy")
+                .about("Copy files and directories")
+                .arg(
+                    Arg::from("-s, --sources=<sources> 'Files or directories to copy'")
+                        .takes_value(true)
+                        .required(true)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::from("-d, --dest=<dest> 'Destination file, or directory to copy the sources into'")
+                        .takes_value(true)
+                        .required(true)
+                )
+                .arg(
+                    Arg::from("-r, --recursive 'Copy directories and their contents'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--preserve=<attrs> 'Comma separated list of mode, timestamps, ownership, xattr or all'")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-n, --no-clobber 'Do not overwrite existing files'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-u, --update 'Only overwrite files older than the source'")
+                        .takes_value(false)
+                        .required(false)
+                        .conflicts_with("no-clobber")
+                )
+                .arg(
+                    Arg::from("--progress 'Show a progress bar even for small copies'")
+                        .takes_value(false)
+                        .required(false)
+                )
+        )
+        .subcommand(
+            App::new("move")
+                .about("Move or rename files and directories")
+                .arg(
+                    Arg::from("-s, --sources=<sources> 'Files or directories to move'")
+                        .takes_value(true)
+                        .required(true)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::from("-d, --dest=<dest> 'New name, or directory to move the sources into'")
+                        .takes_value(true)
+                        .required(true)
+                )
+                .arg(
+                    Arg::from("-n, --no-clobber 'Do not overwrite existing files'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--backup=[suffix] 'Keep files that would be overwritten with this suffix appended (default: ~)'")
+                        .required(false)
+                        .min_values(0)
+                        .require_equals(true)
+                        .default_missing_value("~")
+                        .conflicts_with("no-clobber")
+                )
+                .arg(
+                    Arg::from("--no-journal 'Do not record the operation for undo'")
+                        .takes_value(false)
+                        .required(false)
+                )
+        )
+        .subcommand(
+            App::new("rename")
+                .about("Rename the files matching find-style patterns, replacing text in their names")
+                .arg(
+                    Arg::from("-d, --dirs=<dirs> 'Set of directories'")
+                        .takes_value(true)
+                        .required(true)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::from("-p, --patterns=<patterns> 'Regexes selecting the files to rename, as in find'")
+                        .takes_value(true)
+                        .required(true)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::from("-r, --replace=<replace> 'Text to replace in the names and its replacement, {n} or {n:03} insert a counter'")
+                        .takes_value(true)
+                        .required(false)
+                        .number_of_values(2),
+                )
+                .arg(
+                    Arg::from("-R, --recursive 'Also rename files in subdirectories'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--case=<case> 'Change the case of the new names'")
+                        .takes_value(true)
+                        .required(false)
+                        .possible_values(&["lower", "upper", "title"])
+                )
+                .arg(
+                    Arg::from("--start=<start> 'First value of the {n} counter (default: 1)'")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-n, --dry-run 'Only show the new names'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--no-journal 'Do not record the operation for undo'")
+                        .takes_value(false)
+                        .required(false)
+                )
+        )
+        .subcommand(
+            App::new("ls")
+                .about("List directory contents")
+                .arg(
+                    Arg::from("-d, --dirs=<dirs> 'Directories to list (default: .)'")
+                        .takes_value(true)
+                        .required(false)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::from("-l, --long 'Show mode, owner, group, size and modification time'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-H, --human-readable 'Show sizes like 1.5 KiB'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-s, --sort=<sort> 'Sort by name, size (largest first) or time (newest first)'")
+                        .takes_value(true)
+                        .required(false)
+                        .possible_values(&["name", "size", "time"])
+                )
+                .arg(
+                    Arg::from("-r, --reverse 'Reverse the sort order'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-a, --all 'Include entries starting with .'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-t, --tree 'Show subdirectories as a tree'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-L, --depth=<depth> 'Descend at most this many levels with --tree'")
+                        .takes_value(true)
+                        .required(false)
+                        .requires("tree")
+                )
+        )
+        .subcommand(
+            App::new("du")
+                .about("Show disk usage of directories")
+                .arg(
+                    Arg::from("-d, --dirs=<dirs> 'Directories to measure (default: .)'")
+                        .takes_value(true)
+                        .required(false)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::from("--max-depth=<depth> 'Only report entries this many levels down'")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--top=<n> 'Only report the N largest files and directories'")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-t, --threshold=<size> 'Leave out entries smaller than SIZE, e.g. 100M'")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-b, --apparent-size 'Sort and filter by apparent size instead of allocated size'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-H, --human-readable 'Show sizes like 1.5 KiB'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-a, --all 'Report files too, not only directories'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-j, --threads=<n> 'Number of directories to read in parallel (default: one per core)'")
+                        .takes_value(true)
+                        .required(false)
+                )
+        )
+        .subcommand(
+            App::new("dupes")
+                .about("Find files with identical contents")
+                .arg(
+                    Arg::from("-d, --dirs=<dirs> 'Set of directories'")
+                        .takes_value(true)
+                        .required(true)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::from("-p, --patterns=<patterns> 'Only consider files whose path matches these regexes'")
+                        .takes_value(true)
+                        .required(false)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::from("--min-size=<size> 'Ignore files smaller than SIZE (default: 1)'")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--delete-keep-first 'Delete all but the first file of each set'")
+                        .takes_value(false)
+                        .required(false)
+                        .conflicts_with_all(&["hardlink", "reflink"])
+                )
+                .arg(
+                    Arg::from("--hardlink 'Replace duplicates with hard links to the first file'")
+                        .takes_value(false)
+                        .required(false)
+                        .conflicts_with("reflink")
+                )
+                .arg(
+                    Arg::from("--reflink 'Replace duplicates with reflinked copies of the first file'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--apply 'Carry out the action, by default it is only shown'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--json 'Print the duplicate sets as JSON'")
+                        .takes_value(false)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("--no-journal 'Do not record the operation for undo'")
+                        .takes_value(false)
+                        .required(false)
+                )
+        )
+        .subcommand(
+            App::new("hash")
+                .about("Compute or verify file checksums")
+                .arg(
+                    Arg::from("-d, --dirs=<dirs> 'Set of directories'")
+                        .takes_value(true)
+                        .required(false)
+                        .required_unless_present("check")
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::from("-p, --patterns=<patterns> 'Only hash files whose path matches these regexes'")
+                        .takes_value(true)
+                        .required(false)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::from("-a, --algorithm=<algorithm> 'Digest to compute (default: sha256)'")
+                        .takes_value(true)
+                        .required(false)
+                        .possible_values(&["sha256", "blake3", "crc32"])
+                )
+                .arg(
+                    Arg::from("-o, --output=<output> 'Write the manifest to a file instead of stdout'")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-c, --check=<manifest> 'Verify the files listed in a manifest'")
+                        .takes_value(true)
+                        .required(false)
+                        .conflicts_with_all(&["dirs", "patterns", "output"])
+                )
+        )
+        .subcommand(
+            App::new("watch")
+                .about("Report changed files and run a command when they change")
+                .arg(
+                    Arg::from("-d, --dirs=<dirs> 'Directories to watch, with their subdirectories'")
+                        .takes_value(true)
+                        .required(true)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::from("-p, --patterns=<patterns> 'Only react to files whose path matches these regexes'")
+                        .takes_value(true)
+                        .required(false)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::from("-x, --exec=<cmd> 'Command to run after each batch of changes'")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-r, --replace=<replace_str> 'Replace this argument of the command with the changed files'")
+                        .takes_value(true)
+                        .required(false)
+                        .requires("exec")
+                )
+                .arg(
+                    Arg::from("-a, --all 'Run the command once with all changed files instead of once per file'")
+                        .takes_value(false)
+                        .required(false)
+                        .requires_all(&["replace", "exec"])
+                )
+                .arg(
+                    Arg::from("--debounce=<ms> 'Wait until nothing changed for this long (default: 200)'")
+                        .takes_value(true)
+                        .required(false)
+                )
+        )
+        .subcommand(
+            App::new("undo")
+                .about("Revert a tr, remove or add operation")
+                .arg(
+                    Arg::from("[id] 'Operation to undo, defaults to the most recent one'")
+                        .required(false)
+                )
+                .arg(
+                    Arg::from("-l, --list 'List the operations that can be undone'")
+                        .takes_value(false)
+                        .required(false)
+                        .conflicts_with("id")
+                )
+        )
+        .subcommand(
+            App::new("snapshot")
+                .about("Record the state of a directory tree and compare it later")
+                .subcommand(
+                    App::new("save")
+                        .about("Record path, size, mtime, mode and hash of every file")
+                        .arg(
+                            Arg::from("[dir] 'Directory to record (default: .)'")
+                                .required(false)
+                        )
+                        .arg(
+                            Arg::from("-o, --output=<output> 'Write the snapshot to a file instead of stdout'")
+                                .takes_value(true)
+                                .required(false)
+                        )
+                )
+                .subcommand(
+                    App::new("diff")
+                        .about("Show what changed in a directory since a snapshot")
+                        .arg(
+                            Arg::from("<snapshot> 'Snapshot file written by snapshot save'")
+                                .required(true)
+                        )
+                        .arg(
+                            Arg::from("[dir] 'Directory to compare (default: .)'")
+                                .required(false)
+                        )
+                )
+        )
+        .subcommand(
+            App::new("archive")
+                .about("Pack files into a tar, tar.gz or zip archive and unpack them again")
+                .subcommand(
+                    App::new("create")
+                        .about("Archive the files matching the patterns, keeping mode and mtime")
+                        .arg(
+                            Arg::from("-o, --output=<output> 'Archive to write, the format follows the extension (.tar, .tar.gz, .tgz, .zip)'")
+                                .takes_value(true)
+                                .required(true)
+                        )
+                        .arg(
+                            Arg::from("-d, --dirs=<dirs> 'Directories to archive'")
+                                .takes_value(true)
+                                .required(true)
+                                .multiple_values(true)
+                        )
+                        .arg(
+                            Arg::from("-p, --patterns=<patterns> 'Only archive files matching these patterns, as with find'")
+                                .takes_value(true)
+                                .required(false)
+                                .multiple_values(true)
+                        )
+                )
+                .subcommand(
+                    App::new("extract")
+                        .about("Unpack an archive, refusing paths and symlinks that leave the destination")
+                        .arg(
+                            Arg::from("<archive> 'Archive to unpack'")
+                                .required(true)
+                        )
+                        .arg(
+                            Arg::from("-C, --dest=<dest> 'Directory to unpack into (default: .)'")
+                                .takes_value(true)
+                                .required(false)
+                        )
+                )
+                .subcommand(
+                    App::new("list")
+                        .about("List the files in an archive")
+                        .arg(
+                            Arg::from("<archive> 'Archive to list'")
+                                .required(true)
+                        )
+                )
+        )
+        .subcommand(
+            App::new("trash")
+                .about("Inspect the trash used by remove --trash")
+                .subcommand(App::new("list").about("List trashed files"))
+                .subcommand(
+                    App::new("restore")
+                        .about("Move trashed files back to where they were")
+                        .arg(
+                            Arg::from("<names>... 'Trash names or original paths of the files to restore'")
+                                .required(true)
+                        )
+                )
+                .subcommand(App::new("empty").about("Permanently delete everything in the trash"))
         )
         .get_matches();
     // .get_matches_from(vec!["rust", "find", "--patterns=.*/.rs", "--output=./tests.out", "--dirs=./"]);
 
+    let log = match LogConfig::from_args(&matches).build() {
+        Ok(log) => log,
+        Err(err) => panic!("{}", err),
+    };
+
     if let Some(sub_m) = matches.subcommand_matches("find") {
-        let args = FindConfig::from_args(sub_m);
+        let args = FindConfig::from_args(sub_m, &log);
 
         if let Err(err) = run_find(&args) {
             //Error handling here!
             panic!("{}", err)
         }
     } else if let Some(sub_m) = matches.subcommand_matches("add") {
-        let args = AddConfig::from_args(sub_m);
+        let args = AddConfig::from_args(sub_m, &log);
 
         if let Err(err) = run_add(&args) {
             panic!("{}", err)
         }
     } else if let Some(sub_m) = matches.subcommand_matches("remove") {
-        let args = RemoveConfig::from_args(sub_m);
+        let args = RemoveConfig::from_args(sub_m, &log);
 
         if let Err(err) = run_remove(&args) {
             panic!("{}", err)
         }
     } else if let Some(sub_m) = matches.subcommand_matches("tr") {
-        let args = TrConfig::from_args(sub_m);
+        let args = TrConfig::from_args(sub_m, &log);
 
         if let Err(err) = run_tr(&args) {
             panic!("{}", err)
         }
     } else if let Some(sub_m) = matches.subcommand_matches("grep") {
-        let args = GrepConfig::from_args(sub_m);
+        let args = GrepConfig::from_args(sub_m, &log);
 
         if let Err(err) = run_grep(&args) {
             panic!("{}", err)
         }
+    } else if let Some(sub_m) = matches.subcommand_matches("undo") {
+        let args = UndoConfig::from_args(sub_m, &log);
+
+        if let Err(err) = run_undo(&args) {
+            panic!("{}", err)
+        }
+    } else if let Some(sub_m) = matches.subcommand_matches("trash") {
+        let args = TrashConfig::from_args(sub_m, &log);
+
+        if let Err(err) = run_trash(&args) {
+            panic!("{}", err)
+        }
+    } else if let Some(sub_m) = matches.subcommand_matches("copy") {
+        let args = CopyConfig::from_args(sub_m, &log);
+
+        if let Err(err) = run_copy(&args) {
+            panic!("{}", err)
+        }
+    } else if let Some(sub_m) = matches.subcommand_matches("move") {
+        let args = MoveConfig::from_args(sub_m, &log);
+
+        if let Err(err) = run_move(&args) {
+            panic!("{}", err)
+        }
+    } else if let Some(sub_m) = matches.subcommand_matches("dupes") {
+        let args = DupesConfig::from_args(sub_m, &log);
+
+        if let Err(err) = run_dupes(&args) {
+            panic!("{}", err)
+        }
+    } else if let Some(sub_m) = matches.subcommand_matches("du") {
+        let args = DuConfig::from_args(sub_m, &log);
+
+        if let Err(err) = run_du(&args) {
+            panic!("{}", err)
+        }
+    } else if let Some(sub_m) = matches.subcommand_matches("hash") {
+        let args = HashConfig::from_args(sub_m, &log);
+
+        if let Err(err) = run_hash(&args) {
+            panic!("{}", err)
+        }
+    } else if let Some(sub_m) = matches.subcommand_matches("snapshot") {
+        let args = SnapshotConfig::from_args(sub_m, &log);
+
+        if let Err(err) = run_snapshot(&args) {
+            panic!("{}", err)
+        }
+    } else if let Some(sub_m) = matches.subcommand_matches("archive") {
+        let args = ArchiveConfig::from_args(sub_m, &log);
+
+        if let Err(err) = run_archive(&args) {
+            panic!("{}", err)
+        }
+    } else if let Some(sub_m) = matches.subcommand_matches("watch") {
+        let args = WatchConfig::from_args(sub_m, &log);
+
+        if let Err(err) = run_watch(&args) {
+            panic!("{}", err)
+        }
+    } else if let Some(sub_m) = matches.subcommand_matches("ls") {
+        let args = LsConfig::from_args(sub_m, &log);
+
+        if let Err(err) = run_ls(&args) {
+            panic!("{}", err)
+        }
+    } else if let Some(sub_m) = matches.subcommand_matches("rename") {
+        let args = RenameConfig::from_args(sub_m, &log);
+
+        if let Err(err) = run_rename(&args) {
+            panic!("{}", err)
+        }
     }
 }
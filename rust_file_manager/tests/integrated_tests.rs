@@ -1,9 +1,10 @@
 #[cfg(test)]
 mod tests {
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_main() {
         use clap::{App, Arg}; // tell Rust you will use these two structs in clap
-        use lib::{run_find, FindConfig}; // tell Rust you will use these two things from our "lib" module
+        use lib::{discard_logger, run_find, FindConfig}; // tell Rust you will use these two things from our "lib" module
         let matches = App::new("rust_find")
             .version("0.1.0")
             .author("Your Name <you.email@umd.edu>") 
@@ -35,7 +36,7 @@ mod tests {
 //            .get_matches();
             .get_matches_from(vec!["rust-find", "--patterns=.*/.rs", "--output=./tests.out", "--dirs", "./src", "./tests"]);
 
-        let args = FindConfig::from_args(&matches); // will be defined later
+        let args = FindConfig::from_args(&matches, &discard_logger()); // will be defined later
 
         if let Err(err) = run_find(&args) {
             //Error handling here!
@@ -46,7 +47,7 @@ mod tests {
     }
     #[test]
     fn test_parse_dirs() {
-        use lib::FindConfig;
+        use lib::{discard_logger, FindConfig};
         let dirs = vec!["."];
         let patterns = vec!["."];
         // all ok
@@ -58,6 +59,11 @@ mod tests {
             exec: None,
             replace: None,
             all: false,
+            watch: false,
+            debounce: None,
+            archives: false,
+            color: None,
+            log: discard_logger(),
         };
         let res = cfg.parse_dirs();
         assert!(res.is_ok());
@@ -82,7 +88,7 @@ mod tests {
 
     #[test]
     fn test_parse_size() {
-        use lib::FindConfig;
+        use lib::{discard_logger, FindConfig};
         let dirs = vec!["/dev/null"];
         let patterns = vec!["."];
         // expect some
@@ -91,10 +97,15 @@ mod tests {
             dirs,
             patterns,
             output: None,
-            size: None,
+            size,
             exec: None,
             replace: None,
             all: false,
+            watch: false,
+            debounce: None,
+            archives: false,
+            color: None,
+            log: discard_logger(),
         };
         let res = cfg.parse_size();
         assert!(res.is_some());
@@ -120,7 +131,7 @@ mod tests {
 
     #[test]
     fn test_parse_patterns() {
-        use lib::FindConfig;
+        use lib::{discard_logger, FindConfig};
         let dirs = vec![".", ".."];
         let patterns = vec!["."];
         // all valid regex
@@ -132,6 +143,11 @@ mod tests {
             exec: None,
             replace: None,
             all: false,
+            watch: false,
+            debounce: None,
+            archives: false,
+            color: None,
+            log: discard_logger(),
         };
         let res = cfg.parse_patterns();
         assert!(res.is_ok());
@@ -166,4 +182,1694 @@ mod tests {
         let myfile = MyFile::from_path(&path);
         assert!(myfile.is_err())
     }
+
+    // fresh, empty directory under the system temp dir for tests that touch the filesystem
+    fn scratch_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rfm_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_atomic_write() {
+        use lib::{atomic_write, discard_logger, WriteOptions};
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        let dir = scratch_dir("atomic_write");
+        let target = dir.join("test.txt");
+        fs::write(&target, "Hey Hi Ho").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o640)).unwrap();
+
+        let opts = WriteOptions {
+            backup: Some(".bak"),
+            preserve_mtime: false,
+        };
+        assert!(atomic_write(&target, b"Hi Hi Ho", &opts, &discard_logger()).is_ok());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "Hi Hi Ho");
+        assert_eq!(
+            fs::read_to_string(dir.join("test.txt.bak")).unwrap(),
+            "Hey Hi Ho"
+        );
+        // mode is carried over and no temp files are left behind
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_text_file_round_trip() {
+        use lib::{LineEnding, TextEncoding, TextFile};
+        // Latin-1 with CRLF line endings
+        let latin1 = b"caf\xe9\r\nna\xefve\r\n";
+        let text = TextFile::decode(latin1, None).unwrap();
+        assert_eq!(text.encoding, TextEncoding::Latin1);
+        assert_eq!(text.line_ending, LineEnding::CrLf);
+        assert_eq!(text.content, "caf\u{e9}\nna\u{ef}ve\n");
+        assert_eq!(text.encode(&text.content).unwrap(), latin1.to_vec());
+
+        // UTF-16LE with a byte order mark
+        let utf16: Vec<u8> = [0xFF, 0xFE, b'H', 0, b'i', 0].to_vec();
+        let text = TextFile::decode(&utf16, None).unwrap();
+        assert_eq!(text.encoding, TextEncoding::Utf16Le);
+        assert_eq!(text.content, "Hi");
+        assert_eq!(
+            text.encode("Ho").unwrap(),
+            vec![0xFF, 0xFE, b'H', 0, b'o', 0]
+        );
+
+        // an explicit utf-8 leaves the byte order mark in place
+        let utf8_bom = b"\xef\xbb\xbfHi\n";
+        let text = TextFile::decode(utf8_bom, Some(TextEncoding::Utf8)).unwrap();
+        assert_eq!(text.content, "Hi\n");
+        assert_eq!(text.encode("Ho\n").unwrap(), b"\xef\xbb\xbfHo\n".to_vec());
+
+        // explicit encoding that doesn't fit the bytes
+        assert!(TextFile::decode(latin1, Some(TextEncoding::Utf8)).is_err());
+    }
+
+    #[test]
+    fn test_replace_bytes() {
+        use lib::replace_bytes;
+        assert_eq!(
+            replace_bytes(b"\x00Hey\xff Hey", b"Hey", b"Hi"),
+            b"\x00Hi\xff Hi"
+        );
+        assert_eq!(replace_bytes(b"abc", b"", b"x"), b"abc");
+    }
+
+    #[test]
+    fn test_edit_selected_lines() {
+        use lib::{edit_selected_lines, parse_line_range, LineSelection};
+        use regex::Regex;
+        assert_eq!(parse_line_range("10:50"), Some((10, 50)));
+        assert_eq!(parse_line_range(":5"), Some((1, 5)));
+        assert_eq!(parse_line_range("3"), Some((3, 3)));
+        assert_eq!(parse_line_range("5:2"), None);
+        assert_eq!(parse_line_range("0:2"), None);
+
+        let config = "[client]\nport=1\n[server]\nport=1\n[other]\nport=1\n";
+        let edit = |s: &str| s.replace("port=1", "port=2");
+        let selection = LineSelection {
+            lines: None,
+            after: None,
+            between: Some((
+                Regex::new(r"^\[server\]").unwrap(),
+                Regex::new(r"^\[").unwrap(),
+            )),
+        };
+        assert_eq!(
+            edit_selected_lines(config, &selection, edit),
+            "[client]\nport=1\n[server]\nport=2\n[other]\nport=1\n"
+        );
+
+        let selection = LineSelection {
+            lines: Some((1, 4)),
+            after: Some(Regex::new("client").unwrap()),
+            between: None,
+        };
+        assert_eq!(
+            edit_selected_lines(config, &selection, edit),
+            "[client]\nport=2\n[server]\nport=2\n[other]\nport=1\n"
+        );
+    }
+
+    #[test]
+    fn test_journal_undo() {
+        use lib::{discard_logger, Journal};
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::UNIX_EPOCH;
+        let log = discard_logger();
+        let dir = scratch_dir("journal_undo");
+        let journal = Journal::at(&dir.join("journal"));
+        let modified = dir.join("modified.txt");
+        let removed = dir.join("removed.txt");
+        let created = dir.join("created.txt");
+        fs::write(&modified, "before").unwrap();
+        fs::write(&removed, "keep me").unwrap();
+
+        let mut record = journal.begin("test").unwrap();
+        assert!(record.same_filesystem(&fs::metadata(&removed).unwrap()));
+        record.save_contents(&modified).unwrap();
+        fs::write(&modified, "after").unwrap();
+        record.move_aside(&removed, &log).unwrap();
+        record.created(&created, 0).unwrap();
+        fs::write(&created, "").unwrap();
+        record.commit().unwrap();
+        assert!(!removed.exists());
+        assert_eq!(journal.entries().len(), 1);
+
+        // nothing recorded, nothing kept
+        journal.begin("noop").unwrap().commit().unwrap();
+        assert_eq!(journal.entries().len(), 1);
+
+        let entry = journal.undo(None, &log).unwrap();
+        assert_eq!(entry.command, "test");
+        assert_eq!(fs::read_to_string(&modified).unwrap(), "before");
+        assert_eq!(fs::read_to_string(&removed).unwrap(), "keep me");
+        assert!(!created.exists());
+        assert!(journal.entries().is_empty());
+        assert!(journal.undo(None, &log).is_err());
+
+        // times are restored without opening the file, so it needn't be readable
+        let touched = dir.join("touched.txt");
+        fs::write(&touched, "").unwrap();
+        fs::set_permissions(&touched, fs::Permissions::from_mode(0o200)).unwrap();
+        let before = fs::metadata(&touched).unwrap();
+        let mut record = journal.begin("add").unwrap();
+        record.touched(&touched, &before).unwrap();
+        record.commit().unwrap();
+        let epoch = fs::FileTimes::new().set_modified(UNIX_EPOCH);
+        fs::File::options()
+            .write(true)
+            .open(&touched)
+            .unwrap()
+            .set_times(epoch)
+            .unwrap();
+        journal.undo(None, &log).unwrap();
+        let after = fs::metadata(&touched).unwrap();
+        assert_eq!(after.modified().unwrap(), before.modified().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_journal_prune() {
+        use lib::{discard_logger, Journal, Settings};
+        use std::fs;
+        let log = discard_logger();
+        let dir = scratch_dir("journal_prune");
+        let journal = Journal::at(&dir.join("journal"));
+        let mut ids = Vec::new();
+        for i in 0..4 {
+            let file = dir.join(format!("{}.txt", i));
+            fs::write(&file, vec![b'x'; 64 * 1024]).unwrap();
+            let mut record = journal.begin("test").unwrap();
+            record.move_aside(&file, &log).unwrap();
+            ids.push(record.entry.id.clone());
+            record.commit().unwrap();
+        }
+
+        // the oldest go first
+        assert_eq!(journal.prune(3, u64::MAX, &log), vec![ids[0].clone()]);
+        let left: Vec<String> = journal.entries().into_iter().map(|e| e.id).collect();
+        assert_eq!(left, ids[1..].to_vec());
+
+        // a size cap drops entries until the rest fits
+        assert_eq!(journal.prune(10, 100 * 1024, &log).len(), 2);
+        assert_eq!(journal.entries().len(), 1);
+        assert_eq!(journal.prune(10, 0, &log), vec![ids[3].clone()]);
+        assert!(journal.entries().is_empty());
+
+        let settings = Settings::parse("journal_max_entries = 5\njournal_max_size = 10M\n", &log);
+        assert_eq!(settings.journal_max_entries, 5);
+        assert_eq!(settings.journal_max_size, 10 * 1024 * 1024);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trash() {
+        use lib::{discard_logger, percent_decode_path, percent_encode_path, Settings, Trash};
+        use std::fs;
+        use std::path::Path;
+        let log = discard_logger();
+        let dir = scratch_dir("trash");
+        let trash = Trash::at(&dir.join("Trash")).unwrap();
+        let file = dir.join("my file.txt");
+        fs::write(&file, "one").unwrap();
+        let first = trash.put(&file, &log).unwrap();
+        fs::write(&file, "two").unwrap();
+        let second = trash.put(&file, &log).unwrap();
+        assert!(!file.exists());
+        // same name trashed twice gets a unique trash name
+        assert_eq!(first.name, "my file.txt");
+        assert_eq!(second.name, "my file.txt.2");
+        let info = fs::read_to_string(&first.info).unwrap();
+        assert!(info.starts_with("[Trash Info]\nPath=/"));
+        assert!(info.contains("my%20file.txt\n"));
+
+        let items = trash.list();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].path, file);
+        trash.restore(&items[1], &log).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "two");
+        // can't restore over an existing file
+        assert!(trash.restore(&items[0], &log).is_err());
+        assert_eq!(trash.empty().unwrap(), 1);
+        assert!(trash.list().is_empty());
+
+        let path = Path::new("/tmp/a b/100%/é");
+        assert_eq!(percent_decode_path(&percent_encode_path(path)), path);
+        assert!(Settings::parse("# comment\ntrash = true\n", &log).trash);
+        assert!(!Settings::parse("", &log).trash);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_recursive_and_pattern() {
+        use lib::{discard_logger, is_protected_path, run_remove, RemoveConfig};
+        use std::fs;
+        let dir = scratch_dir("remove_recursive");
+        fs::create_dir_all(dir.join("tree/sub")).unwrap();
+        fs::create_dir_all(dir.join("logs/old")).unwrap();
+        fs::write(dir.join("tree/sub/a.txt"), "a").unwrap();
+        fs::write(dir.join("logs/1.log"), "").unwrap();
+        fs::write(dir.join("logs/keep.txt"), "").unwrap();
+        fs::write(dir.join("logs/old/2.log"), "").unwrap();
+        let d = dir.to_str().unwrap();
+        let logs = dir.join("logs");
+
+        // directories need -r, missing files are ignored with --force
+        let cfg = RemoveConfig {
+            dirs: vec![d],
+            files: vec!["tree", "missing"],
+            patterns: vec![],
+            recursive: false,
+            force: true,
+            journal: false,
+            trash: false,
+            dry_run: false,
+            interactive: false,
+            interactive_once: false,
+            threshold: Some("3"),
+            shred: None,
+            log: discard_logger(),
+        };
+        assert!(run_remove(&cfg).is_ok());
+        assert!(dir.join("tree").exists());
+        let cfg = RemoveConfig {
+            recursive: true,
+            ..cfg
+        };
+        assert!(run_remove(&cfg).is_ok());
+        assert!(!dir.join("tree").exists());
+
+        // patterns only look at the top level without -r
+        let cfg = RemoveConfig {
+            dirs: vec![logs.to_str().unwrap()],
+            files: vec![],
+            patterns: vec![r"\.log$"],
+            recursive: false,
+            ..cfg
+        };
+        assert!(run_remove(&cfg).is_ok());
+        assert!(!logs.join("1.log").exists());
+        assert!(logs.join("old/2.log").exists());
+        let cfg = RemoveConfig {
+            recursive: true,
+            ..cfg
+        };
+        assert!(run_remove(&cfg).is_ok());
+        assert!(!logs.join("old/2.log").exists());
+        assert!(logs.join("keep.txt").exists());
+
+        assert!(is_protected_path(std::path::Path::new("/")));
+        assert!(is_protected_path(std::path::Path::new(".")));
+        assert!(is_protected_path(std::path::Path::new("..")));
+        assert!(!is_protected_path(&logs));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_prompts() {
+        use lib::{discard_logger, human_size, run_remove_with, Prompt, RemoveConfig};
+        use std::fs;
+        // answers questions from a script and remembers what was asked
+        struct Scripted {
+            answers: Vec<bool>,
+            asked: Vec<String>,
+        }
+        impl Prompt for Scripted {
+            fn confirm(&mut self, question: &str) -> bool {
+                self.asked.push(question.to_string());
+                self.answers.remove(0)
+            }
+        }
+
+        let dir = scratch_dir("remove_prompts");
+        for f in ["a", "b", "c", "d"] {
+            fs::write(dir.join(f), "1234").unwrap();
+        }
+        let cfg = RemoveConfig {
+            dirs: vec![dir.to_str().unwrap()],
+            files: vec!["a", "b", "c", "d"],
+            patterns: vec![],
+            recursive: false,
+            force: false,
+            journal: false,
+            trash: false,
+            dry_run: true,
+            interactive: false,
+            interactive_once: false,
+            threshold: Some("3"),
+            shred: None,
+            log: discard_logger(),
+        };
+        let mut prompt = Scripted {
+            answers: vec![],
+            asked: vec![],
+        };
+        assert!(run_remove_with(&cfg, &mut prompt).is_ok());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+
+        // -I asks once for more than 3 files, declining keeps everything
+        let cfg = RemoveConfig {
+            dry_run: false,
+            interactive_once: true,
+            ..cfg
+        };
+        let mut prompt = Scripted {
+            answers: vec![false],
+            asked: vec![],
+        };
+        assert!(run_remove_with(&cfg, &mut prompt).is_ok());
+        assert_eq!(prompt.asked, vec!["remove 4 item(s)?"]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+
+        // -i asks for every file
+        let cfg = RemoveConfig {
+            interactive_once: false,
+            interactive: true,
+            ..cfg
+        };
+        let mut prompt = Scripted {
+            answers: vec![true, false, true, false],
+            asked: vec![],
+        };
+        assert!(run_remove_with(&cfg, &mut prompt).is_ok());
+        assert_eq!(prompt.asked.len(), 4);
+        assert!(!dir.join("a").exists());
+        assert!(dir.join("b").exists());
+        assert!(!dir.join("c").exists());
+
+        // an invalid threshold is an error, not the default
+        let cfg = RemoveConfig {
+            threshold: Some("many"),
+            ..cfg
+        };
+        assert!(run_remove_with(&cfg, &mut prompt).is_err());
+        assert!(dir.join("b").exists());
+        let cfg = RemoveConfig {
+            threshold: Some("3"),
+            shred: Some("abc"),
+            ..cfg
+        };
+        assert!(run_remove_with(&cfg, &mut prompt).is_err());
+        let cfg = RemoveConfig {
+            shred: Some("0"),
+            ..cfg
+        };
+        assert!(run_remove_with(&cfg, &mut prompt).is_err());
+        assert!(dir.join("b").exists());
+
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_shred_file() {
+        use lib::{discard_logger, shred_file};
+        use std::fs;
+        let dir = scratch_dir("shred");
+        let secret = dir.join("secret.txt");
+        fs::write(&secret, "top secret").unwrap();
+        // a second link lets us look at the data after the name is gone
+        fs::hard_link(&secret, dir.join("link")).unwrap();
+
+        assert!(shred_file(&secret, 2, &discard_logger()).is_ok());
+        assert!(!secret.exists());
+        assert_eq!(fs::read(dir.join("link")).unwrap(), vec![0u8; 10]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_add_touch() {
+        use lib::{discard_logger, parse_touch_date, parse_touch_stamp, run_add, AddConfig};
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::{Duration, UNIX_EPOCH};
+        let dir = scratch_dir("add_touch");
+        fs::write(dir.join("existing.txt"), "keep me").unwrap();
+        // touching needs no read permission
+        fs::set_permissions(dir.join("existing.txt"), fs::Permissions::from_mode(0o200)).unwrap();
+        let cfg = AddConfig {
+            dirs: vec![dir.to_str().unwrap()],
+            files: vec!["existing.txt", "new.txt"],
+            journal: false,
+            access_only: false,
+            modify_only: true,
+            no_create: false,
+            date: Some("@1000000000"),
+            reference: None,
+            stamp: None,
+            parents: false,
+            mode: None,
+            template: None,
+            content: None,
+            log: discard_logger(),
+        };
+        assert!(run_add(&cfg).is_ok());
+        // existing files are not truncated, only their times change
+        assert_eq!(
+            fs::read_to_string(dir.join("existing.txt")).unwrap(),
+            "keep me"
+        );
+        let meta = fs::metadata(dir.join("existing.txt")).unwrap();
+        assert_eq!(
+            meta.modified().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_000_000_000)
+        );
+        let meta = fs::metadata(dir.join("new.txt")).unwrap();
+        assert_eq!(meta.len(), 0);
+        assert_eq!(
+            meta.modified().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_000_000_000)
+        );
+        assert_ne!(meta.accessed().unwrap(), meta.modified().unwrap());
+
+        let cfg = AddConfig {
+            files: vec!["other.txt"],
+            no_create: true,
+            ..cfg
+        };
+        assert!(run_add(&cfg).is_ok());
+        assert!(!dir.join("other.txt").exists());
+
+        assert_eq!(
+            parse_touch_date("2001-09-09T01:46:40Z"),
+            Ok(UNIX_EPOCH + Duration::from_secs(1_000_000_000))
+        );
+        assert!(parse_touch_date("2021-02-30").is_err());
+        assert!(parse_touch_stamp("202101021504.05").is_ok());
+        assert!(parse_touch_stamp("2101021504").is_ok());
+        assert!(parse_touch_stamp("01021504").is_ok());
+        assert!(parse_touch_stamp("13021504").is_err());
+        assert!(parse_touch_stamp("0102150").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_add_parents_and_content() {
+        use lib::{discard_logger, fill_template, run_add, AddConfig};
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        let dir = scratch_dir("add_parents");
+        let cfg = AddConfig {
+            dirs: vec![dir.to_str().unwrap()],
+            files: vec!["src/bin/main.rs"],
+            journal: false,
+            access_only: false,
+            modify_only: false,
+            no_create: false,
+            date: None,
+            reference: None,
+            stamp: None,
+            parents: true,
+            mode: Some("600"),
+            template: None,
+            content: Some("// {{name}} in {{dir}}\n"),
+            log: discard_logger(),
+        };
+        assert!(run_add(&cfg).is_ok());
+        let file = dir.join("src/bin/main.rs");
+        assert_eq!(fs::read_to_string(&file).unwrap(), "// main.rs in bin\n");
+        let mode = fs::metadata(&file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // content is only for new files
+        let cfg = AddConfig {
+            content: Some("replaced"),
+            ..cfg
+        };
+        assert!(run_add(&cfg).is_ok());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "// main.rs in bin\n");
+
+        // no -p, no file
+        let cfg = AddConfig {
+            files: vec!["missing/file.rs"],
+            parents: false,
+            ..cfg
+        };
+        assert!(run_add(&cfg).is_ok());
+        assert!(!dir.join("missing").exists());
+
+        let filled = fill_template("{{date}}", &file);
+        assert_eq!(filled.len(), "YYYY-MM-DD".len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_braces() {
+        use lib::expand_braces;
+        assert_eq!(
+            expand_braces("{src,tests}/mod.rs").unwrap(),
+            vec!["src/mod.rs", "tests/mod.rs"]
+        );
+        assert_eq!(
+            expand_braces("log_{01..03}.txt").unwrap(),
+            vec!["log_01.txt", "log_02.txt", "log_03.txt"]
+        );
+        assert_eq!(expand_braces("{0..10..5}").unwrap(), vec!["0", "5", "10"]);
+        assert_eq!(expand_braces("{3..1}").unwrap(), vec!["3", "2", "1"]);
+        assert_eq!(expand_braces("{a..e..2}").unwrap(), vec!["a", "c", "e"]);
+        assert_eq!(
+            expand_braces("{a,b{1,2}}.{x,y}").unwrap(),
+            vec!["a.x", "a.y", "b1.x", "b1.y", "b2.x", "b2.y"]
+        );
+        // not brace expressions
+        assert_eq!(expand_braces("{}").unwrap(), vec!["{}"]);
+        assert_eq!(expand_braces("{single}.txt").unwrap(), vec!["{single}.txt"]);
+        assert_eq!(expand_braces("open{a,b").unwrap(), vec!["open{a,b"]);
+        assert_eq!(expand_braces("{x}{1..2}").unwrap(), vec!["{x}1", "{x}2"]);
+        // huge expansions are refused up front, extreme values don't overflow
+        assert!(expand_braces("{1..9223372036854775807}").is_err());
+        assert!(expand_braces("{0..999}{0..999}").is_err());
+        assert_eq!(
+            expand_braces("{-9223372036854775808..-2..-9223372036854775808}").unwrap(),
+            vec!["-9223372036854775808"]
+        );
+    }
+
+    #[test]
+    fn test_copy_tree() {
+        use lib::{
+            copy_path, discard_logger, run_copy, CopyConfig, CopyOptions, Preserve, Progress,
+        };
+        use std::fs;
+        use std::io::{Seek, SeekFrom, Write};
+        use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+        let log = discard_logger();
+
+        let dir = scratch_dir("copy_tree");
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("nested")).unwrap();
+        fs::write(src.join("a.txt"), "alpha").unwrap();
+        fs::write(src.join("nested/b.txt"), "beta").unwrap();
+        fs::set_permissions(src.join("a.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+        std::os::unix::fs::symlink("a.txt", src.join("link")).unwrap();
+
+        // sparse file: 1 MiB hole followed by some data
+        let mut sparse = fs::File::create(src.join("sparse")).unwrap();
+        sparse.seek(SeekFrom::Start(1 << 20)).unwrap();
+        sparse.write_all(b"tail").unwrap();
+        drop(sparse);
+
+        let opts = CopyOptions {
+            preserve: Preserve::parse("mode,timestamps").unwrap(),
+            ..CopyOptions::default()
+        };
+        let dst = dir.join("dst");
+        copy_path(&src, &dst, &opts, &mut Progress::hidden(), &log).unwrap();
+
+        assert_eq!(fs::read_to_string(dst.join("a.txt")).unwrap(), "alpha");
+        assert_eq!(
+            fs::read_to_string(dst.join("nested/b.txt")).unwrap(),
+            "beta"
+        );
+        assert_eq!(
+            fs::read_link(dst.join("link")).unwrap(),
+            std::path::PathBuf::from("a.txt")
+        );
+        let meta = fs::metadata(dst.join("a.txt")).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+        assert_eq!(
+            meta.modified().unwrap(),
+            fs::metadata(src.join("a.txt")).unwrap().modified().unwrap()
+        );
+        let copied = fs::read(dst.join("sparse")).unwrap();
+        assert_eq!(copied.len(), (1 << 20) + 4);
+        assert!(copied.ends_with(b"tail"));
+        assert!(copied[..1 << 20].iter().all(|b| *b == 0));
+        assert!(fs::metadata(dst.join("sparse")).unwrap().blocks() < 1024);
+
+        // --no-clobber keeps the existing file
+        fs::write(src.join("a.txt"), "changed").unwrap();
+        let opts = CopyOptions {
+            no_clobber: true,
+            ..CopyOptions::default()
+        };
+        copy_path(&src, &dst, &opts, &mut Progress::hidden(), &log).unwrap();
+        assert_eq!(fs::read_to_string(dst.join("a.txt")).unwrap(), "alpha");
+
+        // copying a file onto itself, or a hard link to it, must not truncate it
+        let a = src.join("a.txt");
+        let opts = CopyOptions::default();
+        let err = copy_path(&a, &a, &opts, &mut Progress::hidden(), &log).unwrap_err();
+        assert!(err.to_string().contains("are the same file"));
+        fs::hard_link(&a, dir.join("hard")).unwrap();
+        assert!(copy_path(&a, &dir.join("hard"), &opts, &mut Progress::hidden(), &log).is_err());
+        assert_eq!(fs::read_to_string(&a).unwrap(), "changed");
+        // nor through a symlink that points back at it
+        let link = dir.join("link_to_a");
+        std::os::unix::fs::symlink(&a, &link).unwrap();
+        let err = copy_path(&a, &link, &opts, &mut Progress::hidden(), &log).unwrap_err();
+        assert!(err.to_string().contains("are the same file"));
+        assert_eq!(fs::read_to_string(&a).unwrap(), "changed");
+
+        // FIFOs are recreated rather than read, read-only directories still get their contents
+        let special = dir.join("special");
+        fs::create_dir_all(special.join("ro")).unwrap();
+        fs::write(special.join("ro/inside.txt"), "x").unwrap();
+        fs::set_permissions(special.join("ro"), fs::Permissions::from_mode(0o500)).unwrap();
+        let fifo = std::ffi::CString::new(special.join("pipe").to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+        let opts = CopyOptions {
+            preserve: Preserve::parse("mode,timestamps").unwrap(),
+            ..CopyOptions::default()
+        };
+        let special_copy = dir.join("special_copy");
+        copy_path(
+            &special,
+            &special_copy,
+            &opts,
+            &mut Progress::hidden(),
+            &log,
+        )
+        .unwrap();
+        assert!(fs::metadata(special_copy.join("pipe"))
+            .unwrap()
+            .file_type()
+            .is_fifo());
+        assert_eq!(
+            fs::read_to_string(special_copy.join("ro/inside.txt")).unwrap(),
+            "x"
+        );
+        let mode = fs::metadata(special_copy.join("ro")).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o500);
+        fs::set_permissions(special.join("ro"), fs::Permissions::from_mode(0o700)).unwrap();
+        fs::set_permissions(special_copy.join("ro"), fs::Permissions::from_mode(0o700)).unwrap();
+
+        // a failed entry fails the whole copy, the rest is still copied
+        let partial = dir.join("partial");
+        fs::create_dir_all(&partial).unwrap();
+        fs::write(partial.join("nested"), "in the way").unwrap();
+        assert!(copy_path(&src, &partial, &opts, &mut Progress::hidden(), &log).is_err());
+        assert_eq!(
+            fs::read_to_string(partial.join("a.txt")).unwrap(),
+            "changed"
+        );
+
+        // a directory can't be copied into itself under another spelling
+        let alias = dir.join("alias");
+        std::os::unix::fs::symlink(&src, &alias).unwrap();
+        let inner = alias.join("nested");
+        let cfg = CopyConfig {
+            sources: vec![src.to_str().unwrap()],
+            dest: inner.to_str().unwrap(),
+            recursive: true,
+            preserve: None,
+            no_clobber: false,
+            update: false,
+            progress: false,
+            log: discard_logger(),
+        };
+        assert!(run_copy(&cfg).is_ok());
+        assert!(!src.join("nested/src").exists());
+
+        assert!(Preserve::parse("mode,colour").is_err());
+        assert_eq!(
+            Preserve::parse("all").unwrap(),
+            Preserve {
+                mode: true,
+                timestamps: true,
+                ownership: true,
+                xattr: true
+            }
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_move() {
+        use lib::{copy_and_remove, discard_logger, move_path_no_clobber, run_move, MoveConfig};
+        use std::fs;
+
+        let dir = scratch_dir("move");
+        let a = dir.join("a.txt");
+        let b = dir.join("b.txt");
+        fs::write(&a, "new").unwrap();
+        fs::write(&b, "old").unwrap();
+
+        let err = move_path_no_clobber(&a, &b, &discard_logger()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&b).unwrap(), "old");
+
+        let (a_str, b_str) = (a.to_str().unwrap(), b.to_str().unwrap());
+        let cfg = MoveConfig {
+            sources: vec![a_str],
+            dest: b_str,
+            no_clobber: true,
+            backup: None,
+            journal: false,
+            log: discard_logger(),
+        };
+        assert!(run_move(&cfg).is_ok());
+        assert!(a.exists());
+
+        let cfg = MoveConfig {
+            no_clobber: false,
+            backup: Some(".bak"),
+            ..cfg
+        };
+        assert!(run_move(&cfg).is_ok());
+        assert!(!a.exists());
+        assert_eq!(fs::read_to_string(&b).unwrap(), "new");
+        assert_eq!(fs::read_to_string(dir.join("b.txt.bak")).unwrap(), "old");
+
+        // when the move fails the backup goes back to its place, here because
+        // the source is gone once its backup has been taken
+        let cfg = MoveConfig {
+            sources: vec![b_str],
+            backup: Some(".orig"),
+            ..cfg
+        };
+        assert!(run_move(&cfg).is_ok());
+        assert_eq!(fs::read_to_string(&b).unwrap(), "new");
+        assert!(!dir.join("b.txt.orig").exists());
+
+        // into a directory
+        let sub = dir.join("sub");
+        fs::create_dir(&sub).unwrap();
+        let cfg = MoveConfig {
+            dest: sub.to_str().unwrap(),
+            backup: Some(".bak"),
+            ..cfg
+        };
+        assert!(run_move(&cfg).is_ok());
+        assert_eq!(fs::read_to_string(sub.join("b.txt")).unwrap(), "new");
+
+        // nor into itself under another spelling
+        let alias = dir.join("alias");
+        std::os::unix::fs::symlink(&dir, &alias).unwrap();
+        let inner = alias.join("sub");
+        let cfg = MoveConfig {
+            sources: vec![sub.to_str().unwrap()],
+            dest: inner.to_str().unwrap(),
+            ..cfg
+        };
+        assert!(run_move(&cfg).is_ok());
+        assert!(sub.join("b.txt").exists());
+        assert!(!sub.join("sub").exists());
+
+        // across filesystems the source is only deleted after a complete copy
+        let tree = dir.join("tree");
+        fs::create_dir_all(tree.join("nested")).unwrap();
+        fs::write(tree.join("nested/c.txt"), "keep").unwrap();
+        let blocked = dir.join("blocked");
+        fs::create_dir(&blocked).unwrap();
+        fs::write(blocked.join("nested"), "in the way").unwrap();
+        assert!(copy_and_remove(&tree, &blocked, false, &discard_logger()).is_err());
+        assert_eq!(
+            fs::read_to_string(tree.join("nested/c.txt")).unwrap(),
+            "keep"
+        );
+        let moved = dir.join("moved");
+        copy_and_remove(&tree, &moved, false, &discard_logger()).unwrap();
+        assert!(!tree.exists());
+        assert_eq!(
+            fs::read_to_string(moved.join("nested/c.txt")).unwrap(),
+            "keep"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rename() {
+        use lib::{
+            check_renames, discard_logger, fill_counter, order_renames, plan_renames, run_rename,
+            NameCase, RenameConfig,
+        };
+        use std::fs;
+        let log = discard_logger();
+
+        assert_eq!(fill_counter("img_{n:03}", 7), "img_007");
+        assert_eq!(fill_counter("{n}-{n:3}|{x}", 12), "12- 12|{x}");
+        // the extension keeps its case
+        assert_eq!(
+            NameCase::Title.apply("my holiday-PHOTO.jpg"),
+            "My Holiday-Photo.jpg"
+        );
+        assert_eq!(NameCase::Upper.apply(".bashrc"), ".BASHRC");
+
+        let dir = scratch_dir("rename");
+        fs::create_dir(dir.join("sub")).unwrap();
+        for f in ["o.txt", "oo.txt", "c.md", "sub/o.txt"] {
+            fs::write(dir.join(f), f).unwrap();
+        }
+
+        // two files onto one name, or onto a file that isn't renamed
+        let files = vec![dir.join("1x.txt"), dir.join("x1.txt")];
+        let plan = plan_renames(&files, Some(&["x", ""]), None, 1);
+        assert!(check_renames(&plan, &log).is_err());
+        let plan = plan_renames(&[dir.join("c.txt")], Some(&["txt", "md"]), None, 1);
+        assert!(check_renames(&plan, &log).is_err());
+
+        // x <-> y goes through a temporary name
+        let (x, y) = (dir.join("x"), dir.join("y"));
+        let cycle = vec![(x.clone(), y.clone()), (y.clone(), x.clone())];
+        let steps = order_renames(&cycle);
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[0].0, x);
+        assert_eq!(steps[2].1, y);
+
+        let cfg = RenameConfig {
+            dirs: vec![dir.to_str().unwrap()],
+            patterns: vec![r"\.txt$"],
+            replace: vec![],
+            recursive: false,
+            case: None,
+            start: None,
+            dry_run: false,
+            journal: false,
+            log: discard_logger(),
+        };
+        assert!(run_rename(&cfg).is_err());
+        let cfg = RenameConfig {
+            replace: vec!["", "y"],
+            ..cfg
+        };
+        assert!(run_rename(&cfg).is_err());
+
+        // o -> oo has to wait until oo -> oooo is done, sub/ needs -R
+        let cfg = RenameConfig {
+            replace: vec!["o", "oo"],
+            ..cfg
+        };
+        assert!(run_rename(&cfg).is_ok());
+        assert_eq!(fs::read_to_string(dir.join("oo.txt")).unwrap(), "o.txt");
+        assert_eq!(fs::read_to_string(dir.join("oooo.txt")).unwrap(), "oo.txt");
+        assert!(!dir.join("o.txt").exists());
+        assert!(dir.join("sub/o.txt").exists());
+
+        let cfg = RenameConfig {
+            patterns: vec![r"\.md$"],
+            replace: vec!["c", "readme-{n:02}"],
+            case: Some("upper"),
+            ..cfg
+        };
+        assert!(run_rename(&cfg).is_ok());
+        assert!(dir.join("README-01.md").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ls() {
+        use lib::{discard_logger, ls_lines, tree_lines, Colors, LsOptions, LsSort, MyFile};
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        let log = discard_logger();
+
+        let dir = scratch_dir("ls");
+        fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+        fs::write(dir.join("big.txt"), vec![b'x'; 8192]).unwrap();
+        fs::write(dir.join("small.txt"), "x").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+        fs::write(dir.join("sub/inner.txt"), "").unwrap();
+        std::os::unix::fs::symlink("small.txt", dir.join("link")).unwrap();
+        fs::set_permissions(dir.join("small.txt"), fs::Permissions::from_mode(0o640)).unwrap();
+
+        let file = MyFile::from_path(&dir.join("small.txt")).unwrap();
+        assert_eq!(file.mode_string(), "-rw-r-----");
+        assert!(MyFile::from_path_no_follow(&dir.join("link"))
+            .unwrap()
+            .is_symlink());
+
+        let mut opts = LsOptions {
+            long: false,
+            human: false,
+            sort: LsSort::Name,
+            reverse: false,
+            all: false,
+        };
+        assert_eq!(
+            ls_lines(&dir, &opts, &Colors::default(), &log),
+            vec!["big.txt", "link -> small.txt", "small.txt", "sub/"]
+        );
+        opts.all = true;
+        assert_eq!(
+            ls_lines(&dir, &opts, &Colors::default(), &log)[0],
+            ".hidden"
+        );
+
+        opts.all = false;
+        opts.sort = LsSort::Size;
+        opts.long = true;
+        opts.human = true;
+        let lines = ls_lines(&dir, &opts, &Colors::default(), &log);
+        assert!(lines[0].starts_with("-rw-"));
+        assert!(lines[0].contains("8.0 KiB"));
+        assert!(lines[0].ends_with(" big.txt"));
+
+        opts.long = false;
+        opts.human = false;
+        opts.sort = LsSort::Name;
+        let tree = tree_lines(&dir, &opts, None, &Colors::default(), &log);
+        assert_eq!(
+            tree[1..],
+            [
+                "├── big.txt",
+                "├── link -> small.txt",
+                "├── small.txt",
+                "└── sub/",
+                "    ├── deeper/",
+                "    └── inner.txt",
+                "",
+                "2 directories, 4 files",
+            ]
+        );
+        let tree = tree_lines(&dir, &opts, Some(1), &Colors::default(), &log);
+        assert_eq!(tree.last().unwrap(), "1 directory, 3 files");
+
+        // names that aren't UTF-8 are still listed
+        use std::os::unix::ffi::OsStrExt;
+        let bad = std::ffi::OsStr::from_bytes(b"bad\xffname");
+        fs::write(dir.join("sub").join(bad), "").unwrap();
+        assert_eq!(
+            ls_lines(&dir.join("sub"), &opts, &Colors::default(), &log),
+            vec!["bad\u{FFFD}name", "deeper/", "inner.txt"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_du() {
+        use lib::{discard_logger, disk_usage, du_lines, parse_human_size, DuOptions};
+        use std::fs;
+        let log = discard_logger();
+
+        assert_eq!(parse_human_size("1500"), Some(1500));
+        assert_eq!(parse_human_size("1.5K"), Some(1536));
+        assert_eq!(parse_human_size("2MiB"), Some(2 << 20));
+        assert_eq!(parse_human_size("10 parsecs"), None);
+
+        let dir = scratch_dir("du");
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+        fs::write(dir.join("a/one.txt"), vec![b'x'; 1000]).unwrap();
+        fs::write(dir.join("a/b/two.txt"), vec![b'x'; 3000]).unwrap();
+        // a second name for the same inode is not counted again
+        fs::hard_link(dir.join("a/b/two.txt"), dir.join("a/same.txt")).unwrap();
+        // names that aren't UTF-8 still count, directories included
+        use std::os::unix::ffi::OsStrExt;
+        let odd = dir.join("a").join(std::ffi::OsStr::from_bytes(b"odd\xff"));
+        fs::create_dir(&odd).unwrap();
+        fs::write(odd.join("three.txt"), vec![b'x'; 500]).unwrap();
+        // sparse: 1 MiB apparent, next to nothing allocated
+        fs::File::create(dir.join("sparse"))
+            .unwrap()
+            .set_len(1 << 20)
+            .unwrap();
+
+        let tree = disk_usage(&dir, false, &log);
+        // the directory itself plus the files directly in it
+        let direct = tree.apparent - tree.children.iter().map(|c| c.apparent).sum::<u64>();
+        assert_eq!(direct - fs::metadata(&dir).unwrap().len(), 1 << 20);
+        assert!(tree.allocated < tree.apparent);
+        let a = &tree.children[0];
+        let a_dirs = fs::metadata(dir.join("a")).unwrap().len()
+            + fs::metadata(dir.join("a/b")).unwrap().len()
+            + fs::metadata(&odd).unwrap().len();
+        assert_eq!(a.apparent - a_dirs, 4500);
+
+        let opts = DuOptions {
+            max_depth: Some(1),
+            apparent: true,
+            ..DuOptions::default()
+        };
+        let lines = du_lines(&tree, &opts);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("/a"));
+
+        let tree = disk_usage(&dir, true, &log);
+        let opts = DuOptions {
+            top: Some(2),
+            apparent: true,
+            ..DuOptions::default()
+        };
+        let lines = du_lines(&tree, &opts);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("/sparse"));
+        assert!(lines[1].ends_with("/a"));
+
+        let opts = DuOptions {
+            threshold: Some(1 << 20),
+            apparent: true,
+            all: true,
+            ..DuOptions::default()
+        };
+        assert!(du_lines(&tree, &opts)
+            .iter()
+            .all(|l| !l.contains("one.txt")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dupes() {
+        use lib::{discard_logger, find_duplicates, run_dupes, DupesConfig, MyFile};
+        use std::fs;
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = scratch_dir("dupes");
+        let big: Vec<u8> = (0..20000u32).map(|i| (i % 251) as u8).collect();
+        let mut other = big.clone();
+        // same size, first and last blocks, differs in the middle
+        other[10000] ^= 1;
+        fs::write(dir.join("a"), &big).unwrap();
+        fs::write(dir.join("b"), &big).unwrap();
+        fs::write(dir.join("c"), &other).unwrap();
+        fs::write(dir.join("empty1"), "").unwrap();
+        fs::write(dir.join("empty2"), "").unwrap();
+        fs::hard_link(dir.join("a"), dir.join("a_link")).unwrap();
+        std::os::unix::fs::symlink("a", dir.join("symlink")).unwrap();
+
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        let files: Vec<MyFile> = names
+            .iter()
+            .map(|n| MyFile::from_path(&dir.join(n)).unwrap())
+            .collect();
+        let sets = find_duplicates(files, 1, &discard_logger());
+        assert_eq!(sets.len(), 1);
+        assert_eq!(sets[0].files, vec![dir.join("a"), dir.join("b")]);
+        assert_eq!(sets[0].wasted(), 20000);
+
+        // dry run by default
+        let dir_str = dir.to_str().unwrap();
+        let cfg = DupesConfig {
+            dirs: vec![dir_str],
+            patterns: vec![],
+            min_size: None,
+            action: Some("delete"),
+            apply: false,
+            json: true,
+            journal: false,
+            log: discard_logger(),
+        };
+        assert!(run_dupes(&cfg).is_ok());
+        assert!(dir.join("b").exists());
+
+        let cfg = DupesConfig {
+            action: Some("hardlink"),
+            apply: true,
+            ..cfg
+        };
+        assert!(run_dupes(&cfg).is_ok());
+        let ino = |n: &str| fs::metadata(dir.join(n)).unwrap().ino();
+        assert_eq!(ino("a"), ino("b"));
+        assert_ne!(ino("a"), ino("c"));
+        assert_eq!(fs::read(dir.join("b")).unwrap(), big);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hash() {
+        use lib::{
+            check_manifest, discard_logger, hash_file, manifest_line, parse_manifest_line,
+            CheckStatus, HashAlgorithm,
+        };
+        use std::fs;
+        use std::path::PathBuf;
+
+        let dir = scratch_dir("hash");
+        let file = dir.join("abc.txt");
+        fs::write(&file, "abc").unwrap();
+        assert_eq!(
+            hash_file(&file, HashAlgorithm::Sha256).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hash_file(&file, HashAlgorithm::Blake3).unwrap(),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(hash_file(&file, HashAlgorithm::Crc32).unwrap(), "352441c2");
+
+        // names sha256sum has to escape
+        let odd = PathBuf::from("a\\b\nc");
+        let line = manifest_line("00ff", &odd);
+        assert_eq!(line, "\\00ff  a\\\\b\\nc");
+        assert_eq!(parse_manifest_line(&line), Some(("00ff".to_string(), odd)));
+        assert_eq!(
+            parse_manifest_line("00FF *bin.dat"),
+            Some(("00ff".to_string(), PathBuf::from("bin.dat")))
+        );
+        assert_eq!(parse_manifest_line("not a checksum"), None);
+
+        let good = hash_file(&file, HashAlgorithm::Sha256).unwrap();
+        let manifest = format!(
+            "{}\n{}\n{}\n",
+            manifest_line(&good, &file),
+            manifest_line(&"0".repeat(64), &file),
+            manifest_line(&good, &dir.join("gone.txt"))
+        );
+        let statuses: Vec<CheckStatus> =
+            check_manifest(&manifest, HashAlgorithm::Sha256, &discard_logger())
+                .into_iter()
+                .map(|(_, s)| s)
+                .collect();
+        assert_eq!(
+            statuses,
+            vec![CheckStatus::Ok, CheckStatus::Failed, CheckStatus::Missing]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        use lib::{diff_snapshots, discard_logger, take_snapshot, Snapshot};
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        use std::path::PathBuf;
+        let log = discard_logger();
+
+        let dir = scratch_dir("snapshot");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for (name, content) in [("a", "1"), ("sub/b", "2"), ("c", "3"), ("d", "4")] {
+            fs::write(dir.join(name), content).unwrap();
+        }
+        let before = take_snapshot(&dir, &log);
+        assert_eq!(before.files.len(), 4);
+        assert_eq!(before.files[3].path, PathBuf::from("sub/b"));
+
+        // survives a round trip through JSON
+        let json = serde_json::to_string(&before).unwrap();
+        let before: Snapshot = serde_json::from_str(&json).unwrap();
+
+        fs::write(dir.join("a"), "changed").unwrap();
+        fs::rename(dir.join("sub/b"), dir.join("b")).unwrap();
+        fs::remove_file(dir.join("c")).unwrap();
+        fs::write(dir.join("e"), "new").unwrap();
+        fs::set_permissions(dir.join("d"), fs::Permissions::from_mode(0o700)).unwrap();
+
+        let diff = diff_snapshots(&before, &take_snapshot(&dir, &log));
+        assert_eq!(diff.added, vec![PathBuf::from("e")]);
+        assert_eq!(diff.removed, vec![PathBuf::from("c")]);
+        assert_eq!(diff.modified, vec![PathBuf::from("a"), PathBuf::from("d")]);
+        assert_eq!(
+            diff.renamed,
+            vec![(PathBuf::from("sub/b"), PathBuf::from("b"))]
+        );
+        assert_eq!(diff.lines()[0], "modified  a");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_watcher() {
+        use lib::{discard_logger, ChangeKind, Watcher};
+        use std::fs;
+        use std::time::Duration;
+
+        let dir = scratch_dir("watcher");
+        fs::write(dir.join("old.txt"), "").unwrap();
+        let mut watcher = Watcher::new(&discard_logger()).unwrap();
+        watcher.add_tree(&dir).unwrap();
+
+        let d = dir.clone();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            fs::write(d.join("new.txt"), "1").unwrap();
+            fs::write(d.join("new.txt"), "2").unwrap();
+            fs::write(d.join("old.txt"), "x").unwrap();
+            fs::create_dir_all(d.join("sub")).unwrap();
+            fs::write(d.join("sub/inner.txt"), "").unwrap();
+        });
+        let (debounce, limit) = (Duration::from_millis(200), Duration::from_secs(5));
+        let mut changes = watcher.wait_timeout(debounce, limit).unwrap();
+        writer.join().unwrap();
+        // the file in sub/ may only show up once sub/ is watched
+        if changes.len() < 3 {
+            changes.extend(watcher.wait_timeout(debounce, limit).unwrap());
+        }
+
+        let kind_of = |name: &str| {
+            changes
+                .iter()
+                .find(|c| c.path == dir.join(name))
+                .map(|c| c.kind)
+        };
+        assert_eq!(kind_of("new.txt"), Some(ChangeKind::Created));
+        assert_eq!(kind_of("old.txt"), Some(ChangeKind::Modified));
+        assert_eq!(kind_of("sub/inner.txt"), Some(ChangeKind::Created));
+        assert_eq!(changes.len(), 3);
+
+        fs::remove_file(dir.join("new.txt")).unwrap();
+        let changes = watcher
+            .wait_timeout(Duration::from_millis(100), limit)
+            .unwrap();
+        assert_eq!(changes[0].kind, ChangeKind::Deleted);
+
+        // ignored files and drained changes are never reported
+        fs::write(dir.join("out.txt"), "").unwrap();
+        watcher.drain().unwrap();
+        watcher.ignore(&dir.join("sub/../out.txt"));
+        fs::write(dir.join("out.txt"), "results").unwrap();
+        fs::write(dir.join("build.log"), "log").unwrap();
+        watcher.drain().unwrap();
+        let quiet = Duration::from_millis(300);
+        assert!(watcher.wait_timeout(debounce, quiet).unwrap().is_empty());
+        fs::write(dir.join("out.txt"), "more results").unwrap();
+        assert!(watcher.wait_timeout(debounce, quiet).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_watch_output() {
+        use lib::{discard_logger, run_find, FindConfig};
+        use std::fs;
+        use std::time::Duration;
+
+        let dir = scratch_dir("find_watch_output");
+        fs::write(dir.join("a.txt"), "").unwrap();
+        let out = dir.join("results.out");
+        let (d, o) = (dir.clone(), out.clone());
+        // runs until the test process exits
+        std::thread::spawn(move || {
+            let cfg = FindConfig {
+                dirs: vec![d.to_str().unwrap()],
+                patterns: vec![r"\.txt$"],
+                output: Some(o.to_str().unwrap()),
+                size: None,
+                exec: None,
+                replace: None,
+                all: false,
+                watch: true,
+                debounce: Some("50"),
+                archives: false,
+                color: None,
+                log: discard_logger(),
+            };
+            run_find(&cfg)
+        });
+
+        let pause = Duration::from_millis(500);
+        std::thread::sleep(pause);
+        fs::write(dir.join("b.txt"), "").unwrap();
+        std::thread::sleep(pause);
+        fs::write(dir.join("c.txt"), "").unwrap();
+        std::thread::sleep(pause);
+
+        let text = fs::read_to_string(&out).unwrap();
+        let mut lines: Vec<&str> = text.lines().collect();
+        lines.sort_unstable();
+        let expected: Vec<String> = ["a.txt", "b.txt", "c.txt"]
+            .iter()
+            .map(|n| dir.join(n).to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(lines, expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_archive() {
+        use lib::{
+            create_archive, discard_logger, extract_archive, list_archive, safe_entry_path,
+            symlink_stays_inside, ArchiveFormat,
+        };
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        use std::path::{Path, PathBuf};
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let dir = scratch_dir("archive");
+        fs::create_dir_all(dir.join("src/sub")).unwrap();
+        fs::write(dir.join("src/a.txt"), "a").unwrap();
+        fs::write(dir.join("src/sub/b.sh"), "b").unwrap();
+        fs::set_permissions(dir.join("src/sub/b.sh"), fs::Permissions::from_mode(0o750)).unwrap();
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        fs::File::options()
+            .write(true)
+            .open(dir.join("src/a.txt"))
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        let members = vec![
+            (dir.join("src/a.txt"), PathBuf::from("src/a.txt")),
+            (dir.join("src/sub/b.sh"), PathBuf::from("src/sub/b.sh")),
+        ];
+
+        for name in ["out.tar", "out.tar.gz", "out.zip"] {
+            let archive = dir.join(name);
+            let format = ArchiveFormat::from_path(&archive).unwrap();
+            assert_eq!(create_archive(&archive, format, &members).unwrap(), 2);
+            let listed: Vec<String> = list_archive(&archive, format)
+                .unwrap()
+                .into_iter()
+                .map(|f| f.path)
+                .collect();
+            assert_eq!(listed, vec!["src/a.txt", "src/sub/b.sh"]);
+
+            let dest = dir.join(format!("x_{}", name));
+            assert_eq!(
+                extract_archive(&archive, format, &dest, &discard_logger()).unwrap(),
+                (2, 0)
+            );
+            assert_eq!(fs::read_to_string(dest.join("src/a.txt")).unwrap(), "a");
+            let meta = fs::metadata(dest.join("src/sub/b.sh")).unwrap();
+            assert_eq!(meta.permissions().mode() & 0o777, 0o750);
+            let got = fs::metadata(dest.join("src/a.txt"))
+                .unwrap()
+                .modified()
+                .unwrap();
+            // zip only keeps even seconds
+            assert!(got <= mtime && mtime.duration_since(got).unwrap() < Duration::from_secs(2));
+        }
+        assert_eq!(
+            ArchiveFormat::from_path(Path::new("a.tgz")),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(ArchiveFormat::from_path(Path::new("a.rar")), None);
+
+        let dest = Path::new("/dest");
+        assert_eq!(
+            safe_entry_path(dest, Path::new("./a/b")),
+            Some(PathBuf::from("/dest/a/b"))
+        );
+        assert_eq!(safe_entry_path(dest, Path::new("a/../../b")), None);
+        assert_eq!(safe_entry_path(dest, Path::new("/etc/passwd")), None);
+        assert!(symlink_stays_inside(Path::new("a/l"), Path::new("../b")));
+        assert!(!symlink_stays_inside(
+            Path::new("a/l"),
+            Path::new("../../b")
+        ));
+        assert!(!symlink_stays_inside(
+            Path::new("a/l"),
+            Path::new("b/../../..")
+        ));
+        assert!(!symlink_stays_inside(Path::new("l"), Path::new("/tmp")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_extract_hostile_archive() {
+        use lib::{discard_logger, run_archive, ArchiveConfig};
+        use std::fs;
+        use tar::{Builder, EntryType, Header};
+
+        // raw headers, the tar builder refuses names like these
+        fn member(
+            b: &mut Builder<Vec<u8>>,
+            name: &[u8],
+            kind: EntryType,
+            link: &[u8],
+            data: &[u8],
+        ) {
+            let mut h = Header::new_old();
+            h.as_old_mut().name[..name.len()].copy_from_slice(name);
+            h.as_old_mut().linkname[..link.len()].copy_from_slice(link);
+            h.set_entry_type(kind);
+            h.set_size(data.len() as u64);
+            h.set_mode(0o644);
+            h.set_cksum();
+            b.append(&h, data).unwrap();
+        }
+
+        let dir = scratch_dir("extract_hostile");
+        let dest = dir.join("dest");
+        fs::create_dir_all(dest.join("full")).unwrap();
+        fs::write(dest.join("full/keep.txt"), "keep").unwrap();
+        fs::create_dir(dest.join("empty")).unwrap();
+        fs::write(dir.join("outside.txt"), "secret").unwrap();
+        let abs = dir.join("abs.txt");
+
+        let mut b = Builder::new(Vec::new());
+        member(&mut b, b"../evil.txt", EntryType::Regular, b"", b"x");
+        member(
+            &mut b,
+            abs.to_str().unwrap().as_bytes(),
+            EntryType::Regular,
+            b"",
+            b"x",
+        );
+        member(&mut b, b"up", EntryType::Symlink, b"..", b"");
+        member(&mut b, b"sub/", EntryType::Directory, b"", b"");
+        member(&mut b, b"in", EntryType::Symlink, b"sub", b"");
+        member(&mut b, b"in/x.txt", EntryType::Regular, b"", b"x");
+        member(&mut b, b"hl", EntryType::Link, b"../outside.txt", b"");
+        member(&mut b, b"full", EntryType::Regular, b"", b"clobber");
+        member(&mut b, b"empty", EntryType::Regular, b"", b"replaced");
+        member(&mut b, b"ok.txt", EntryType::Regular, b"", b"ok");
+        let archive = dir.join("hostile.tar");
+        fs::write(&archive, b.into_inner().unwrap()).unwrap();
+
+        let cfg = ArchiveConfig {
+            action: "extract",
+            archive: archive.to_str(),
+            dirs: vec![],
+            patterns: vec![],
+            dest: dest.to_str(),
+            color: None,
+            log: discard_logger(),
+        };
+        // skipped members make the whole extraction fail
+        assert!(run_archive(&cfg).is_err());
+        assert!(!dir.join("evil.txt").exists());
+        assert!(!abs.exists());
+        assert!(fs::symlink_metadata(dest.join("up")).is_err());
+        assert!(fs::symlink_metadata(dest.join("in"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert!(!dest.join("sub/x.txt").exists());
+        assert!(fs::symlink_metadata(dest.join("hl")).is_err());
+        assert_eq!(
+            fs::read_to_string(dir.join("outside.txt")).unwrap(),
+            "secret"
+        );
+        assert_eq!(
+            fs::read_to_string(dest.join("full/keep.txt")).unwrap(),
+            "keep"
+        );
+        assert_eq!(fs::read_to_string(dest.join("empty")).unwrap(), "replaced");
+        assert_eq!(fs::read_to_string(dest.join("ok.txt")).unwrap(), "ok");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_grep_compressed() {
+        use lib::{grep_reader, open_search_reader, CompressionFormat};
+        use regex::Regex;
+        use std::fs;
+        use std::io::Write;
+
+        let dir = scratch_dir("grep_zip");
+        let text = "alpha\nERROR one\r\nbeta\nERROR two\n";
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(text.as_bytes()).unwrap();
+        fs::write(dir.join("app.log.gz"), gz.finish().unwrap()).unwrap();
+        let mut bz = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bz.write_all(text.as_bytes()).unwrap();
+        fs::write(dir.join("app.log.bz2"), bz.finish().unwrap()).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(text.as_bytes()).unwrap();
+        fs::write(dir.join("app.log.xz"), xz.finish().unwrap()).unwrap();
+        // no extension, recognized by its magic bytes
+        fs::write(
+            dir.join("app.log.1"),
+            zstd::encode_all(text.as_bytes(), 0).unwrap(),
+        )
+        .unwrap();
+
+        let rgx = Regex::new("^ERROR").unwrap();
+        for name in ["app.log.gz", "app.log.bz2", "app.log.xz", "app.log.1"] {
+            let mut found: Vec<(usize, String)> = Vec::new();
+            let reader = open_search_reader(&dir.join(name), true).unwrap();
+            grep_reader(reader, &rgx, |n, line| found.push((n, line.to_string()))).unwrap();
+            assert_eq!(
+                found,
+                vec![(2, "ERROR one".to_string()), (4, "ERROR two".to_string())],
+                "{}",
+                name
+            );
+        }
+
+        // without -z the compressed bytes are searched as they are
+        let mut found = 0;
+        let reader = open_search_reader(&dir.join("app.log.gz"), false).unwrap();
+        grep_reader(reader, &rgx, |_, _| found += 1).unwrap();
+        assert_eq!(found, 0);
+        assert_eq!(
+            CompressionFormat::from_path(std::path::Path::new("x.zst")),
+            Some(CompressionFormat::Zstd)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_in_archives() {
+        use lib::{
+            create_archive, discard_logger, for_each_archive_entry, get_matched_files,
+            split_archive_path, ArchiveFormat, MyFile,
+        };
+        use regex::Regex;
+        use std::fs;
+        use std::path::{Path, PathBuf};
+        let log = discard_logger();
+
+        let dir = scratch_dir("find_archives");
+        fs::write(dir.join("main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.join("notes.txt"), "todo").unwrap();
+        let members = vec![
+            (dir.join("main.rs"), PathBuf::from("src/main.rs")),
+            (dir.join("notes.txt"), PathBuf::from("notes.txt")),
+        ];
+        let tar = dir.join("code.tar.gz");
+        create_archive(&tar, ArchiveFormat::TarGz, &members).unwrap();
+        let zip = dir.join("code.zip");
+        create_archive(&zip, ArchiveFormat::Zip, &members).unwrap();
+
+        let pats = vec![Regex::new(r"\.rs$").unwrap()];
+        let mut found: Vec<MyFile> = Vec::new();
+        get_matched_files(&mut found, &dir, &pats, None, false, &log);
+        assert_eq!(found.len(), 1);
+
+        found.clear();
+        get_matched_files(&mut found, &dir, &pats, None, true, &log);
+        let mut paths: Vec<String> = found.iter().map(|f| f.path.clone()).collect();
+        paths.sort();
+        let base = dir.display();
+        assert_eq!(
+            paths,
+            vec![
+                format!("{}/code.tar.gz!/src/main.rs", base),
+                format!("{}/code.zip!/src/main.rs", base),
+                format!("{}/main.rs", base),
+            ]
+        );
+        assert_eq!(
+            found
+                .iter()
+                .find(|f| f.path.contains('!'))
+                .unwrap()
+                .size_bytes,
+            12
+        );
+
+        let virtual_path = format!("{}/code.zip!/notes.txt", base);
+        let (archive, member) = split_archive_path(&virtual_path).unwrap();
+        assert_eq!(archive, zip.as_path());
+        assert_eq!(member, "notes.txt");
+        assert!(split_archive_path("plain!/file").is_none());
+
+        let mut contents: Vec<(String, String)> = Vec::new();
+        for_each_archive_entry(&tar, ArchiveFormat::TarGz, |name, data| {
+            let mut text = String::new();
+            data.read_to_string(&mut text)?;
+            contents.push((name.to_string(), text));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            contents[0],
+            ("src/main.rs".to_string(), "fn main() {}".to_string())
+        );
+        assert_eq!(contents[1].1, "todo");
+        assert!(!Path::new(&virtual_path).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_colors() {
+        use lib::{parse_color, Colors, LsColors, MyFile, SnapshotDiff};
+        use regex::Regex;
+        use std::path::PathBuf;
+        use std::time::UNIX_EPOCH;
+
+        let plain = Colors::default();
+        let rgx = Regex::new("o+").unwrap();
+        assert_eq!(plain.grep_line(3, "foo boo", &rgx), "3: foo boo");
+        assert!(!parse_color(Some("never")).unwrap().is_enabled());
+        assert!(parse_color(Some("always")).unwrap().is_enabled());
+        assert!(parse_color(Some("sometimes")).is_err());
+
+        let colors = Colors::forced(Some("di=01;34:ex=01;32:*.rs=00;33"));
+        assert_eq!(
+            colors.grep_line(3, "foo boo", &rgx),
+            "\x1b[32m3\x1b[0m: f\x1b[01;31moo\x1b[0m b\x1b[01;31moo\x1b[0m"
+        );
+        let file = |path: &str, mode: u32| MyFile::from_archive_entry(path, 0, mode, UNIX_EPOCH);
+        let rs = file("src/main.rs", 0o100755);
+        assert_eq!(colors.file(&rs, &rs.path), "\x1b[00;33msrc/main.rs\x1b[0m");
+        let exe = file("run", 0o100755);
+        assert_eq!(colors.file(&exe, "run"), "\x1b[01;32mrun\x1b[0m");
+        let txt = file("a.txt", 0o100644);
+        assert_eq!(colors.file(&txt, "a.txt"), "a.txt");
+
+        let ls = LsColors::parse("ln=target:fi=0:*.tar.gz=01;31");
+        assert_eq!(ls.code(0o120777, "link"), "");
+        assert_eq!(ls.code(0o100644, "x.tar.gz"), "01;31");
+        assert_eq!(ls.code(0o100644, "x.txt"), "0");
+        assert_eq!(ls.code(0o040755, "dir"), "");
+
+        let diff = SnapshotDiff {
+            added: vec![PathBuf::from("b")],
+            removed: vec![PathBuf::from("a")],
+            modified: Vec::new(),
+            renamed: Vec::new(),
+        };
+        assert_eq!(diff.lines(), vec!["removed   a", "added     b"]);
+        assert_eq!(
+            diff.lines_colored(&colors),
+            vec!["\x1b[31mremoved   a\x1b[0m", "\x1b[32madded     b\x1b[0m"]
+        );
+    }
+
+    #[test]
+    fn test_logging() {
+        use lib::{get_matched_files, parse_regexes, LogConfig};
+        use slog::Level;
+        use std::fs;
+        let dir = scratch_dir("logging");
+        let cfg = |verbose: u64, quiet: bool| LogConfig {
+            verbose,
+            quiet,
+            log_file: None,
+            format: None,
+        };
+        assert_eq!(cfg(0, false).parse_level(), Level::Warning);
+        assert_eq!(cfg(2, false).parse_level(), Level::Debug);
+        assert_eq!(cfg(5, false).parse_level(), Level::Trace);
+        assert_eq!(cfg(0, true).parse_level(), Level::Error);
+        let bad = LogConfig {
+            format: Some("xml"),
+            ..cfg(0, false)
+        };
+        assert!(bad.build().is_err());
+
+        // skipped directories and invalid regexes are recorded as JSON lines
+        let log_file = dir.join("log.json");
+        let log_path = log_file.to_str().unwrap();
+        let log = LogConfig {
+            log_file: Some(log_path),
+            format: Some("json"),
+            ..cfg(1, false)
+        }
+        .build()
+        .unwrap();
+        let pats = parse_regexes(&["(", "a"], &log).unwrap();
+        assert_eq!(pats.len(), 1);
+        let mut files = Vec::new();
+        get_matched_files(&mut files, &dir.join("missing"), &pats, None, false, &log);
+        drop(log);
+
+        let text = fs::read_to_string(&log_file).unwrap();
+        let records: Vec<serde_json::Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["msg"], "Invalid regular expression, ignoring");
+        assert_eq!(records[0]["level"], "WARN");
+        assert_eq!(records[0]["pattern"], "(");
+        let reason = records[0]["error"].as_str().unwrap();
+        assert!(!reason.contains('\n'));
+        assert!(reason.contains("unclosed group"));
+        assert_eq!(records[1]["msg"], "Could not read directory, skipping");
+        // the trace line for each searched directory is filtered out at -v
+        assert!(!text.contains("Searching directory"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }